@@ -0,0 +1,189 @@
+use crate::tts;
+use anyhow::{bail, Context};
+
+/// The abbreviations that are always read out in full, in the format of `ABBREVIATIONS_FILE`.
+///
+/// Only the ones that mean the same wherever they are, `St.` is left out as it can be both a saint
+/// and a street.
+const DEFAULT_ABBREVIATIONS: &str = "\
+# abbreviation = how it is read out
+vs. = versus
+Dr. = doctor
+Mr. = mister
+Mrs. = missus
+e.g. = for example
+i.e. = that is
+etc. = et cetera
+w/ = with
+& = and
+TIL = today I learned
+";
+
+/// Abbreviations Polly mispronounces, with what to read instead.
+#[derive(Debug)]
+pub struct Abbreviations {
+    /// Longest abbreviation first, so `Mrs.` wins from `Mr`
+    substitutions: Vec<(String, String)>,
+}
+
+impl Default for Abbreviations {
+    fn default() -> Abbreviations {
+        Abbreviations::from_table(DEFAULT_ABBREVIATIONS).unwrap()
+    }
+}
+
+impl Abbreviations {
+    /// The default abbreviations, extended and overridden by the ones in the file at `path`.
+    pub fn load(path: &str) -> Result<Abbreviations, anyhow::Error> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+        Abbreviations::from_table(&format!("{}\n{}", DEFAULT_ABBREVIATIONS, contents))
+    }
+
+    /// Parse lines like `vs. = versus`. Empty lines and lines starting with `#` are ignored, and a
+    /// later line for the same abbreviation replaces the earlier one.
+    pub fn from_table(table: &str) -> Result<Abbreviations, anyhow::Error> {
+        let mut substitutions: Vec<(String, String)> = Vec::new();
+        for (index, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (abbreviation, expansion) = match line.rfind('=') {
+                Some(split) => (line[..split].trim(), line[split + 1..].trim()),
+                None => bail!("Line {} is not like `abbreviation = expansion`", index + 1),
+            };
+            if abbreviation.is_empty() || expansion.is_empty() {
+                bail!("Line {} is not like `abbreviation = expansion`", index + 1);
+            }
+            substitutions.retain(|(existing, _)| existing != abbreviation);
+            substitutions.push((abbreviation.to_owned(), expansion.to_owned()));
+        }
+        substitutions.sort_by_key(|(abbreviation, _)| std::cmp::Reverse(abbreviation.len()));
+        Ok(Abbreviations { substitutions })
+    }
+
+    /// `text` with every abbreviation replaced by its expansion.
+    pub fn expand(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        self.for_each_part(text, |part| match part {
+            Part::Text(text) => result.push_str(text),
+            Part::Abbreviation(_, expansion) => result.push_str(expansion),
+        });
+        result
+    }
+
+    /// `text` escaped for SSML, with every abbreviation in a `<sub>` tag with its expansion.
+    /// `None` if the text can't be escaped.
+    pub fn to_ssml(&self, text: &str) -> Option<String> {
+        let mut result = Some(String::with_capacity(text.len()));
+        self.for_each_part(text, |part| {
+            let escaped = match part {
+                Part::Text(text) => tts::escape_xml(text),
+                Part::Abbreviation(abbreviation, expansion) => tts::escape_xml(abbreviation)
+                    .and_then(|abbreviation| {
+                        let expansion = tts::escape_xml(expansion)?;
+                        Some(format!(
+                            "<sub alias=\"{}\">{}</sub>",
+                            expansion, abbreviation
+                        ))
+                    }),
+            };
+            result = match (result.take(), escaped) {
+                (Some(mut result), Some(escaped)) => {
+                    result.push_str(&escaped);
+                    Some(result)
+                }
+                _ => None,
+            };
+        });
+        result
+    }
+
+    /// Split `text` in the abbreviations and the text between them.
+    ///
+    /// Abbreviations only match when they are not part of a longer word, so `TIL` is not found in
+    /// `UNTIL` and `&` not in `salt&pepper`.
+    fn for_each_part<'a>(&'a self, text: &'a str, mut f: impl FnMut(Part<'a>)) {
+        let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+        let mut start = 0;
+        let mut index = 0;
+        while index < text.len() {
+            let before = text[..index].chars().next_back();
+            let found = self.substitutions.iter().find(|(abbreviation, _)| {
+                let rest = &text[index..];
+                if !rest.starts_with(abbreviation.as_str()) {
+                    return false;
+                }
+                let after = rest[abbreviation.len()..].chars().next();
+                !is_word(before) && !is_word(after)
+            });
+            match found {
+                Some((abbreviation, expansion)) => {
+                    if start < index {
+                        f(Part::Text(&text[start..index]));
+                    }
+                    f(Part::Abbreviation(abbreviation, expansion));
+                    index += abbreviation.len();
+                    start = index;
+                }
+                None => index += text[index..].chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        if start < text.len() {
+            f(Part::Text(&text[start..]));
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    /// The abbreviation and its expansion
+    Abbreviation(&'a str, &'a str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_replaces_default_abbreviations() {
+        let abbreviations = Abbreviations::default();
+        assert_eq!(
+            abbreviations.expand("Dr. Jekyll vs. Mrs. Hyde & co, e.g. TIL"),
+            "doctor Jekyll versus missus Hyde and co, for example today I learned"
+        );
+        assert_eq!(abbreviations.expand("221B Baker St."), "221B Baker St.");
+    }
+
+    #[test]
+    fn expand_only_matches_whole_words() {
+        let abbreviations = Abbreviations::default();
+        assert_eq!(
+            abbreviations.expand("UNTIL the Drive, salt&pepper"),
+            "UNTIL the Drive, salt&pepper"
+        );
+    }
+
+    #[test]
+    fn to_ssml_uses_sub_tags() {
+        let abbreviations = Abbreviations::default();
+        assert_eq!(
+            abbreviations.to_ssml("Tom & Jerry's <vs.>").as_deref(),
+            Some(
+                "Tom <sub alias=\"and\">&amp;</sub> Jerry&apos;s \
+                 &lt;<sub alias=\"versus\">vs.</sub>&gt;"
+            )
+        );
+        assert_eq!(abbreviations.to_ssml("Bad \u{1}"), None);
+    }
+
+    #[test]
+    fn from_table_overrides_earlier_lines() {
+        let abbreviations =
+            Abbreviations::from_table("# comment\n\nSt. = saint\nSt. = street\n").unwrap();
+        assert_eq!(abbreviations.expand("Main St."), "Main street");
+        assert!(Abbreviations::from_table("no separator").is_err());
+        assert!(Abbreviations::from_table("= nothing").is_err());
+    }
+}
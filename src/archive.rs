@@ -0,0 +1,150 @@
+use crate::audio::Recording;
+use crate::reddit::RedditPost;
+use crate::storage;
+use crate::tts::OutputFormat;
+use anyhow::Context;
+use log::{info, warn};
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+
+/// Check that `ARCHIVE_DIR` can be written, if it is set.
+pub fn check_writable(archive_dir: Option<&str>) -> Result<(), anyhow::Error> {
+    match archive_dir {
+        Some(dir) => storage::ensure_dir_writable(dir),
+        None => Ok(()),
+    }
+}
+
+/// Save the audio of `post` to `ARCHIVE_DIR/<date>/<id>.<ext>`, in `format`, with the joke itself
+/// in a json file next to it.
+///
+/// Silence can't be added to the encoded audio, so when the `recording` pauses between its parts,
+/// like with `PUNCHLINE_DELAY_MS` without SSML, every part is saved to `<id>-<n>.<ext>` instead.
+/// The json lists the files with the pause before each of them. `RIMSHOT_FILE` isn't archived.
+///
+/// Does nothing if `ARCHIVE_DIR` is not set. Errors are only logged, so a full disk doesn't stop
+/// the joke from being told.
+pub fn save(
+    archive_dir: Option<&str>,
+    format: OutputFormat,
+    post: &RedditPost,
+    voice_id: &str,
+    recording: &Recording,
+) {
+    if let Some(dir) = archive_dir {
+        if let Err(e) = try_save(Path::new(dir), format, post, voice_id, recording) {
+            warn!("Could not archive joke {:?}: {:#}", post.id, e);
+        }
+    }
+}
+
+fn try_save(
+    dir: &Path,
+    format: OutputFormat,
+    post: &RedditPost,
+    voice_id: &str,
+    recording: &Recording,
+) -> Result<(), anyhow::Error> {
+    let dir = dir.join(chrono::Local::now().format("%Y-%m-%d").to_string());
+    std::fs::create_dir_all(&dir).context("Could not create archive directory")?;
+
+    let audio_files = audio_files(&post.id, format.extension(), recording);
+    for (name, _, audio) in &audio_files {
+        std::fs::write(dir.join(name), audio).context("Could not write audio")?;
+    }
+
+    let parts: Vec<_> = audio_files
+        .iter()
+        .map(|(name, pause_before, _)| {
+            json!({ "file": name, "pause_before_ms": pause_before.as_millis() as u64 })
+        })
+        .collect();
+    let details = json!({
+        "id": post.id,
+        "title": post.title,
+        "selftext": post.selftext,
+        "score": post.score,
+        "voice": voice_id,
+        "parts": parts,
+    });
+    let details = serde_json::to_string_pretty(&details)?;
+    std::fs::write(dir.join(format!("{}.json", post.id)), details)
+        .context("Could not write joke details")?;
+
+    info!("Archived joke {:?} to {:?}", post.id, dir);
+    Ok(())
+}
+
+/// The names of the audio files of `recording`, with the pause before them and their audio. A
+/// single file when the parts are played back to back.
+fn audio_files(
+    id: &str,
+    extension: &str,
+    recording: &Recording,
+) -> Vec<(String, Duration, Vec<u8>)> {
+    let pauses = recording
+        .parts()
+        .skip(1)
+        .any(|(pause_before, _)| pause_before > Duration::from_secs(0));
+    if !pauses {
+        let audio = recording
+            .parts()
+            .flat_map(|(_, audio)| audio.iter().copied())
+            .collect();
+        return vec![(
+            format!("{}.{}", id, extension),
+            Duration::from_secs(0),
+            audio,
+        )];
+    }
+    recording
+        .parts()
+        .enumerate()
+        .map(|(index, (pause_before, audio))| {
+            let name = format!("{}-{}.{}", id, index + 1, extension);
+            (name, pause_before, audio.to_vec())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Encoding;
+    use std::sync::Arc;
+
+    fn recording(parts: &[(u64, &[u8])]) -> Recording {
+        let mut recording = Recording::default();
+        for (pause_ms, audio) in parts {
+            recording.push(
+                Duration::from_millis(*pause_ms),
+                Arc::from(*audio),
+                Encoding::File,
+            );
+        }
+        recording
+    }
+
+    #[test]
+    fn audio_files_are_joined_without_pauses() {
+        let files = audio_files("abc", "mp3", &recording(&[(0, b"setup"), (0, b"line")]));
+        assert_eq!(
+            files,
+            vec![(
+                String::from("abc.mp3"),
+                Duration::from_secs(0),
+                b"setupline".to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn audio_files_are_split_at_pauses() {
+        let files = audio_files("abc", "mp3", &recording(&[(0, b"setup"), (1000, b"line")]));
+        let names: Vec<&str> = files.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["abc-1.mp3", "abc-2.mp3"]);
+        assert_eq!(files[1].1, Duration::from_millis(1000));
+        assert_eq!(files[1].2, b"line".to_vec());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::shutdown;
+use anyhow::Context;
+use log::warn;
+use rodio::Source;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where and how the jokes are played
+pub struct AudioConfig {
+    /// Between 0 and 1, see `playback_volume`
+    pub volume: f32,
+    /// Part of the name of the device to play on, `None` for the default device
+    pub device_name: Option<&'static str>,
+    /// Exit instead of only displaying jokes when there is no audio device
+    pub require_audio: bool,
+    /// Played after every joke, `None` to not play anything
+    pub rimshot_file: Option<&'static str>,
+}
+
+/// `PLAYBACK_VOLUME` clamped between 0 and 1.
+pub fn playback_volume(volume: f32) -> f32 {
+    let clamped = volume.clamp(0.0, 1.0);
+    if clamped != volume {
+        warn!(
+            "PLAYBACK_VOLUME {} is out of range, using {} instead",
+            volume, clamped
+        );
+    }
+    clamped
+}
+
+/// Find the first output device whose name contains `name`, ignoring case.
+///
+/// Falls back to the default output device if `name` is `None` or no device matches. Returns
+/// `None` if there is no output device at all, e.g. on a headless server or when a USB DAC has
+/// been unplugged.
+pub fn select_output_device(name: Option<&str>) -> Option<rodio::Device> {
+    if let Some(name) = name {
+        let name = name.to_lowercase();
+        let device = rodio::output_devices().find(|d| d.name().to_lowercase().contains(&name));
+        match device {
+            Some(device) => return Some(device),
+            None => warn!(
+                "Could not find audio device matching {:?}, using the default device",
+                name
+            ),
+        }
+    }
+    rodio::default_output_device()
+}
+
+/// How the audio passed to `play` is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// A mp3, ogg vorbis or wav file, the format is detected from the data
+    File,
+    /// Raw 16-bit signed little-endian mono samples
+    Pcm { sample_rate: u32 },
+}
+
+/// A sink to play the audio of the jokes on `device`, at `volume`.
+///
+/// The sink is meant to be kept for as long as `device` is used. Playback stops when the sink is
+/// dropped.
+pub fn new_sink(device: &rodio::Device, volume: f32) -> rodio::Sink {
+    let mut sink = rodio::Sink::new(device);
+    sink.set_volume(volume);
+    sink
+}
+
+/// Queue `audio` on `sink`, after everything that was queued before.
+///
+/// Playback starts as soon as the first audio is queued.
+pub fn append(
+    sink: &rodio::Sink,
+    audio: Arc<[u8]>,
+    encoding: Encoding,
+) -> Result<(), anyhow::Error> {
+    match encoding {
+        Encoding::File => {
+            let decoder =
+                rodio::Decoder::new(Cursor::new(audio)).context("Could not create decoder")?;
+            sink.append(decoder);
+        }
+        Encoding::Pcm { sample_rate } => {
+            let samples: Vec<i16> = audio
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                .collect();
+            sink.append(rodio::buffer::SamplesBuffer::new(1, sample_rate, samples));
+        }
+    }
+    Ok(())
+}
+
+/// Queue `duration` of silence on `sink`.
+pub fn append_silence(sink: &rodio::Sink, duration: Duration) {
+    if duration > Duration::from_secs(0) {
+        sink.append(rodio::source::Zero::<i16>::new(1, 16000).take_duration(duration));
+    }
+}
+
+/// Queue `RIMSHOT_FILE` on `sink`, if it is set.
+pub fn append_rimshot(sink: &rodio::Sink, rimshot_file: Option<&str>) {
+    if let Some(path) = rimshot_file {
+        match load_rimshot(path) {
+            Ok(rimshot) => sink.append(rimshot),
+            Err(e) => warn!("Could not play RIMSHOT_FILE {:?}: {:#}", path, e),
+        }
+    }
+}
+
+/// Read the file every time, so it can be swapped out without a restart
+fn load_rimshot(path: &str) -> Result<rodio::Decoder<Cursor<Vec<u8>>>, anyhow::Error> {
+    let audio = std::fs::read(path).context("Could not read file")?;
+    let decoder = rodio::Decoder::new(Cursor::new(audio)).context("Could not create decoder")?;
+    Ok(decoder)
+}
+
+/// The audio of a told joke, kept so it can be played again.
+#[derive(Debug, Default)]
+pub struct Recording {
+    /// Every part with the silence before it
+    parts: Vec<(Duration, Arc<[u8]>, Encoding)>,
+}
+
+impl Recording {
+    pub fn push(&mut self, pause_before: Duration, audio: Arc<[u8]>, encoding: Encoding) {
+        self.parts.push((pause_before, audio, encoding));
+    }
+
+    /// Every part, with the silence before it.
+    pub fn parts(&self) -> impl Iterator<Item = (Duration, &[u8])> {
+        self.parts
+            .iter()
+            .map(|(pause_before, audio, _)| (*pause_before, &audio[..]))
+    }
+
+    /// Queue the joke on `sink` again, the way it was played the first time.
+    pub fn replay(
+        &self,
+        sink: &rodio::Sink,
+        rimshot_file: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        for (pause_before, audio, encoding) in &self.parts {
+            append_silence(sink, *pause_before);
+            append(sink, Arc::clone(audio), *encoding)?;
+        }
+        append_rimshot(sink, rimshot_file);
+        Ok(())
+    }
+}
+
+/// Block until the sink has finished playing, or until the program is shutting down.
+///
+/// `on_tick` is called every 100ms while waiting.
+pub async fn wait_for_playback(sink: &rodio::Sink, mut on_tick: impl FnMut()) {
+    while !sink.empty() {
+        on_tick();
+        if shutdown::is_requested() {
+            sink.stop();
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
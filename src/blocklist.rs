@@ -0,0 +1,112 @@
+use crate::reddit::RedditPost;
+use anyhow::Context;
+use log::debug;
+
+/// Words that should never be heard in a joke.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    /// Lowercased, so matching is case-insensitive
+    words: Vec<String>,
+}
+
+impl Blocklist {
+    /// Read a blocklist with one word or phrase per line. Empty lines and lines starting with `#`
+    /// are ignored.
+    pub fn load(path: &str) -> Result<Blocklist, anyhow::Error> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+        Ok(Blocklist::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Blocklist {
+        let words = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect();
+        Blocklist { words }
+    }
+
+    /// The first blocked word in `text`.
+    ///
+    /// Words are matched as substrings rather than whole words, so `ass` also blocks `class`.
+    fn find(&self, text: &str) -> Option<&str> {
+        let text = text.to_lowercase();
+        self.words
+            .iter()
+            .find(|word| text.contains(word.as_str()))
+            .map(String::as_str)
+    }
+}
+
+/// Remove the posts whose title or selftext contains a word in `blocklist`.
+pub fn filter_blocked(posts: Vec<RedditPost>, blocklist: &Blocklist) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            let blocked = blocklist
+                .find(&p.title)
+                .or_else(|| blocklist.find(&p.selftext));
+            if let Some(word) = blocked {
+                debug!("Skipping post containing {:?} {:?}", word, p);
+            }
+            blocked.is_none()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: &str, title: &str, selftext: &str) -> RedditPost {
+        RedditPost {
+            id: String::from(id),
+            title: String::from(title),
+            selftext: String::from(selftext),
+            ..RedditPost::default()
+        }
+    }
+
+    fn ids(posts: &[RedditPost]) -> Vec<&str> {
+        posts.iter().map(|p| p.id.as_str()).collect()
+    }
+
+    #[test]
+    fn blocklist_parse_skips_comments_and_empty_lines() {
+        let blocklist = Blocklist::parse("# family friendly\n\n  Beer \nwine\n");
+        assert_eq!(blocklist.words, vec!["beer", "wine"]);
+    }
+
+    #[test]
+    fn filter_blocked_matches_title_and_selftext_ignoring_case() {
+        let blocklist = Blocklist::parse("beer");
+        let posts = vec![
+            post("title", "A BEER walks into a bar", ""),
+            post("selftext", "A man walks into a bar", "and orders a Beer"),
+            post("clean", "A horse walks into a bar", "why the long face?"),
+        ];
+        assert_eq!(ids(&filter_blocked(posts, &blocklist)), vec!["clean"]);
+    }
+
+    #[test]
+    fn filter_blocked_matches_substrings() {
+        let blocklist = Blocklist::parse("ass");
+        let posts = vec![
+            post("word", "Kick ass", ""),
+            post("inside", "I went to class", ""),
+            post("clean", "Nothing here", ""),
+        ];
+        assert_eq!(ids(&filter_blocked(posts, &blocklist)), vec!["clean"]);
+    }
+
+    #[test]
+    fn filter_blocked_keeps_everything_with_empty_blocklist() {
+        let posts = vec![post("a", "anything", "goes")];
+        assert_eq!(
+            ids(&filter_blocked(posts, &Blocklist::default())),
+            vec!["a"]
+        );
+    }
+}
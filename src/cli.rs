@@ -0,0 +1,720 @@
+use crate::{display, quiet_hours, tts};
+use clap::{crate_authors, crate_name, crate_version, App, Arg, ArgMatches};
+use lazy_static::lazy_static;
+use rusoto_core::Region;
+use std::str::FromStr;
+
+lazy_static! {
+    /// The command line arguments. Every option falls back to the environment variable of the same
+    /// name, so it can also be configured in `.env`.
+    pub static ref ARGS: ArgMatches<'static> = app().get_matches();
+}
+
+fn app() -> App<'static, 'static> {
+    App::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Reads the newest dad jokes from reddit out loud while the space is open")
+        .arg(
+            option("log-format", "LOG_FORMAT")
+                .help("Format of the log output, json prints one object per line")
+                .possible_values(&["plain", "json"])
+                .default_value("plain"),
+        )
+        .arg(
+            option("spacestate-url", "SPACESTATE_URL")
+                .help("Url of the spacestate of the space"),
+        )
+        .arg(
+            option("spacestate-format", "SPACESTATE_FORMAT")
+                .help("Format of the spacestate response")
+                .possible_values(&["pixelbar", "spaceapi"])
+                .default_value("pixelbar"),
+        )
+        .arg(
+            option("spacestate-json-pointer", "SPACESTATE_JSON_POINTER")
+                .help("JSON pointer to the state in the spacestate response, like /state/open, overrides SPACESTATE_FORMAT")
+                .validator(is_json_pointer),
+        )
+        .arg(
+            option("spacestate-open-value", "SPACESTATE_OPEN_VALUE")
+                .help("Value at SPACESTATE_JSON_POINTER when the space is open, json like true or 1, or otherwise a string")
+                .default_value("true"),
+        )
+        .arg(
+            option("open-cycles-required", "OPEN_CYCLES_REQUIRED")
+                .help("Only tell jokes once the space has been open for this many polls in a row")
+                .validator(is_positive)
+                .default_value("1"),
+        )
+        .arg(
+            option("reddit-source", "REDDIT_SOURCE")
+                .help("Where to load jokes from, file reads them from REDDIT_FILE instead of reddit")
+                .possible_values(&["reddit", "file"])
+                .default_value("reddit"),
+        )
+        .arg(
+            option("reddit-file", "REDDIT_FILE")
+                .help("File with a json array of jokes, one json joke per line or a saved reddit listing"),
+        )
+        .arg(
+            option("reddit-url", "REDDIT_URL")
+                .help("Comma-separated list of reddit listing urls to load jokes from, overrides REDDIT_SUBREDDIT"),
+        )
+        .arg(
+            option("reddit-subreddit", "REDDIT_SUBREDDIT")
+                .help("Comma-separated list of subreddits or multireddits like /user/<name>/m/<multi> to load jokes from, if REDDIT_URL is not set"),
+        )
+        .arg(
+            option("reddit-listing", "REDDIT_LISTING")
+                .help("Listing of the subreddits to load")
+                .possible_values(&["new", "hot", "top"])
+                .default_value("new"),
+        )
+        .arg(
+            option("reddit-time", "REDDIT_TIME")
+                .help("Period of the top listing")
+                .possible_values(&["hour", "day", "week", "month", "year", "all"])
+                .default_value("day"),
+        )
+        .arg(
+            option("reddit-limit", "REDDIT_LIMIT")
+                .help("Number of posts to load from every subreddit")
+                .validator(is_positive)
+                .default_value("25"),
+        )
+        .arg(
+            option("reddit-username", "REDDIT_USERNAME")
+                .help("Reddit username of the operator of the bot, sent in the User-Agent"),
+        )
+        .arg(
+            option("mode", "MODE")
+                .help("Tell a joke whenever there is a new one, or only the best joke once a day. For trending, see SELECTION_MODE")
+                .possible_values(&["continuous", "daily"])
+                .default_value("continuous"),
+        )
+        .arg(
+            option("selection-mode", "SELECTION_MODE")
+                .help("How to pick a joke out of the listings")
+                .possible_values(&["top", "weighted", "trending"])
+                .default_value("top"),
+        )
+        .arg(
+            option("trending-gravity", "TRENDING_GRAVITY")
+                .help("How fast posts lose their score with age in trending selection mode")
+                .validator(is::<f64>)
+                .default_value("1.8"),
+        )
+        .arg(
+            option("rng-seed", "RNG_SEED")
+                .help("Seed for picking voices and jokes, so runs with the same posts pick the same")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("selection-score-floor", "SELECTION_SCORE_FLOOR")
+                .help("Minimum weight of a post in weighted selection mode")
+                .validator(is::<f64>)
+                .default_value("1"),
+        )
+        .arg(
+            option("use-top-comment", "USE_TOP_COMMENT")
+                .help("Read the highest scoring comment as the punchline of posts with only a title")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("min-score", "MIN_SCORE")
+                .help("Posts scoring below this are never told")
+                .validator(is::<i64>)
+                .default_value("0"),
+        )
+        .arg(
+            option("min-comments", "MIN_COMMENTS")
+                .help("Posts with fewer comments than this are never told")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("min-upvote-ratio", "MIN_UPVOTE_RATIO")
+                .help("Posts with a lower share of upvotes than this, from 0.0 to 1.0, are never told")
+                .validator(is_optional::<f64>),
+        )
+        .arg(
+            option("skip-nsfw", "SKIP_NSFW")
+                .help("Never tell posts marked as NSFW")
+                .validator(is_bool)
+                .default_value("true"),
+        )
+        .arg(
+            option("skip-removed", "SKIP_REMOVED")
+                .help("Never tell posts that were removed or locked")
+                .validator(is_bool)
+                .default_value("true"),
+        )
+        .arg(
+            option("require-selftext", "REQUIRE_SELFTEXT")
+                .help("Skip link and image posts, and posts with only a title")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("skip-title-phrases", "SKIP_TITLE_PHRASES")
+                .help("Comma separated phrases, posts whose title contains any of them are skipped")
+                .default_value("megathread,rules,announcement"),
+        )
+        .arg(
+            option("skip-authors", "SKIP_AUTHORS")
+                .help("Comma separated users whose posts are skipped")
+                .default_value("AutoModerator"),
+        )
+        .arg(
+            option("skip-flairs", "SKIP_FLAIRS")
+                .help("Comma separated flairs, posts whose flair contains any of them are skipped")
+                .default_value("meta"),
+        )
+        .arg(
+            option("blocklist-file", "BLOCKLIST_FILE").help(
+                "File with one word per line, posts containing any of them are never told",
+            ),
+        )
+        .arg(
+            option("used-ids-backend", "USED_IDS_BACKEND")
+                .help("Where to store which jokes have been told")
+                .possible_values(&["file", "sqlite"])
+                .default_value("file"),
+        )
+        .arg(
+            option("used-ids-file", "USED_IDS_FILE")
+                .help("File to store the told jokes in, for the file backend"),
+        )
+        .arg(
+            option("used-ids-db", "USED_IDS_DB")
+                .help("Sqlite database to store the told jokes in, for the sqlite backend"),
+        )
+        .arg(
+            option("dedupe-by-title", "DEDUPE_BY_TITLE")
+                .help("Also skip posts with the same title as a told joke, to catch reposts")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("recent-window", "RECENT_WINDOW")
+                .help("Don't repeat any of this many last told jokes while there are others, even if they may be told again")
+                .validator(is::<usize>)
+                .default_value("0"),
+        )
+        .arg(
+            option("used-id-ttl-days", "USED_ID_TTL_DAYS")
+                .help("After this many days a joke may be told again, empty to never repeat jokes")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("max-used-ids", "MAX_USED_IDS")
+                .help("Only remember this many of the last told jokes, and history entries, 0 to remember all of them")
+                .validator(is::<usize>)
+                .default_value("0"),
+        )
+        .arg(
+            option("compact-interval-hours", "COMPACT_INTERVAL_HOURS")
+                .help("Forget the jokes past USED_ID_TTL_DAYS and MAX_USED_IDS again every this many hours, on top of at startup")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("aws-polly-access-key", "AWS_POLLY_ACCESS_KEY")
+                .help("Access key of the AWS account used for Polly")
+                .hide_env_values(true),
+        )
+        .arg(
+            option("aws-polly-secret-access-key", "AWS_POLLY_SECRET_ACCESS_KEY")
+                .help("Secret access key of the AWS account used for Polly")
+                .hide_env_values(true),
+        )
+        .arg(
+            option("aws-region", "AWS_REGION")
+                .help("AWS region to use Polly in")
+                .validator(|region| {
+                    Region::from_str(&region)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .default_value("eu-west-1"),
+        )
+        .arg(
+            option("polly-engine", "POLLY_ENGINE")
+                .help("Polly engine to synthesize speech with")
+                .possible_values(&["standard", "neural"])
+                .default_value("standard"),
+        )
+        .arg(
+            option("polly-language-code", "POLLY_LANGUAGE_CODE")
+                .help("Language of the Polly voices")
+                .default_value("en-US"),
+        )
+        .arg(
+            option("polly-voice-id", "POLLY_VOICE_ID")
+                .help("Always use this voice, empty to pick a random voice for every joke"),
+        )
+        .arg(
+            option("voice-gender", "VOICE_GENDER")
+                .help("Only read the jokes with male or female voices")
+                .possible_values(&["any", "male", "female"])
+                .default_value("any"),
+        )
+        .arg(
+            option("voice-refresh-hours", "VOICE_REFRESH_HOURS")
+                .help("Load the available voices again every this many hours, to pick up new voices")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("voice-weights", "VOICE_WEIGHTS")
+                .help("Only pick these voices, e.g. Matthew=3,Joanna=2 picks Matthew 3 out of 5 times")
+                .validator(|weights| tts::parse_voice_weights(&weights).map(|_| ())),
+        )
+        .arg(
+            option("output-format", "OUTPUT_FORMAT")
+                .help("Audio format to get from Polly, pcm is played at 16kHz")
+                .possible_values(&["mp3", "ogg_vorbis", "pcm", "json"])
+                .validator(is_audio_format)
+                .default_value("mp3"),
+        )
+        .arg(
+            option("polly-retries", "POLLY_RETRIES")
+                .help("How many times to try synthesizing a joke while Polly is throttling requests")
+                .validator(is_positive)
+                .default_value("3"),
+        )
+        .arg(
+            option("abbreviations-file", "ABBREVIATIONS_FILE").help(
+                "File with lines like `vs. = versus`, added to the abbreviations that are read out in full",
+            ),
+        )
+        .arg(
+            option("spoken-urls", "SPOKEN_URLS")
+                .help("Read urls in jokes as the word link, leave them out or spell them out")
+                .possible_values(&["link", "remove", "keep"])
+                .default_value("link"),
+        )
+        .arg(
+            option("display-urls", "DISPLAY_URLS")
+                .help("Show urls on screen as they are, instead of like SPOKEN_URLS")
+                .validator(is_bool)
+                .default_value("true"),
+        )
+        .arg(
+            option("show-metadata", "SHOW_METADATA")
+                .help("Show the subreddit and score of every joke at the bottom of the screen")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("use-ssml", "USE_SSML")
+                .help("Add a pause between the setup and the punchline")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("intro-text", "INTRO_TEXT")
+                .help("Read before every joke, like \"Dad joke of the moment:\""),
+        )
+        .arg(
+            option("intro-display", "INTRO_DISPLAY")
+                .help("Also show INTRO_TEXT above every joke")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("speech-rate", "SPEECH_RATE")
+                .help("How fast the jokes are read with USE_SSML, x-slow / slow / medium / fast / x-fast or a percentage like 90%")
+                .validator(is_speech_rate),
+        )
+        .arg(
+            option("speech-pitch", "SPEECH_PITCH")
+                .help("How high the jokes are read with USE_SSML, x-low / low / medium / high / x-high or a change like -10%")
+                .validator(is_speech_pitch),
+        )
+        .arg(
+            option("cache-audio", "CACHE_AUDIO")
+                .help("Reuse previously synthesized audio instead of calling Polly again")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("audio-cache-dir", "AUDIO_CACHE_DIR")
+                .help("Directory to cache synthesized audio in")
+                .default_value("audio_cache"),
+        )
+        .arg(
+            option("polly-stats-file", "POLLY_STATS_FILE")
+                .help("File to keep the total number of characters sent to Polly in")
+                .default_value("polly_stats.txt"),
+        )
+        .arg(
+            option("polly-cost-per-million", "POLLY_COST_PER_MILLION")
+                .help("Price in dollars of one million characters, to estimate the Polly costs")
+                .validator(is::<f64>)
+                .default_value("4.00"),
+        )
+        .arg(
+            option("long-text-mode", "LONG_TEXT_MODE")
+                .help("What to do with jokes longer than MAX_TEXT_CHARS")
+                .possible_values(&["truncate", "chunk"])
+                .default_value("truncate"),
+        )
+        .arg(
+            option("max-text-chars", "MAX_TEXT_CHARS")
+                .help("With LONG_TEXT_MODE=truncate, jokes longer than this are cut off")
+                .validator(is_positive)
+                .default_value("2500"),
+        )
+        .arg(
+            option("tts-fallback", "TTS_FALLBACK")
+                .help("Read the joke with TTS_FALLBACK_COMMAND when Polly is unavailable")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("tts-fallback-command", "TTS_FALLBACK_COMMAND")
+                .help("Local text to speech program that accepts the arguments of espeak")
+                .default_value("espeak"),
+        )
+        .arg(
+            option("archive-dir", "ARCHIVE_DIR")
+                .help("Directory to save the audio of every joke in, empty to not archive jokes"),
+        )
+        .arg(
+            option("history-file", "HISTORY_FILE")
+                .help("File to append every told joke to as a json line, empty to keep no history"),
+        )
+        .arg(
+            option("playback-volume", "PLAYBACK_VOLUME")
+                .help("Volume to play the jokes at, from 0.0 to 1.0")
+                .validator(is::<f32>)
+                .default_value("1.0"),
+        )
+        .arg(
+            option("audio-device-name", "AUDIO_DEVICE_NAME").help(
+                "Part of the name of the audio device to play on, empty for the default device",
+            ),
+        )
+        .arg(
+            option("rimshot-file", "RIMSHOT_FILE")
+                .help("Mp3 or wav file to play after every joke, empty to play nothing"),
+        )
+        .arg(
+            option("require-audio", "REQUIRE_AUDIO")
+                .help("Exit when there is no audio device, instead of only displaying the jokes")
+                .validator(is_bool)
+                .default_value("false"),
+        )
+        .arg(
+            option("title-color", "TITLE_COLOR")
+                .help("Color of the title of the joke")
+                .possible_values(display::COLOR_NAMES)
+                .default_value("yellow"),
+        )
+        .arg(
+            option("text-color", "TEXT_COLOR")
+                .help("Color of the text of the joke")
+                .possible_values(display::COLOR_NAMES)
+                .default_value("default"),
+        )
+        .arg(
+            option("reveal-style", "REVEAL_STYLE")
+                .help("How the text of the joke appears, typewriter types it out after PUNCHLINE_DELAY_MS, or 700ms if that isn't set")
+                .possible_values(&["instant", "typewriter"])
+                .default_value("instant"),
+        )
+        .arg(
+            option("reveal-delay-ms", "REVEAL_DELAY_MS")
+                .help("Milliseconds between every character of the typewriter reveal")
+                .validator(is::<u64>)
+                .default_value("50"),
+        )
+        .arg(
+            option("punchline-delay-ms", "PUNCHLINE_DELAY_MS")
+                .help("Milliseconds between the title and the text, on screen and read out, instead of only a short SSML break")
+                .validator(is_optional::<u64>),
+        )
+        .arg(
+            option("quiet-start", "QUIET_START")
+                .help("Time (HH:MM) at which quiet hours start, when no jokes are read out")
+                .validator(is_time),
+        )
+        .arg(
+            option("quiet-end", "QUIET_END")
+                .help("Time (HH:MM) at which quiet hours end, may be earlier than QUIET_START")
+                .validator(is_time),
+        )
+        .arg(
+            option("quiet-timezone", "QUIET_TIMEZONE")
+                .help("Timezone of QUIET_START and QUIET_END")
+                .possible_values(&["local", "utc"])
+                .default_value("local"),
+        )
+        .arg(
+            option("quiet-display", "QUIET_DISPLAY")
+                .help("Still show jokes during quiet hours, without reading them out")
+                .validator(is_bool)
+                .default_value("true"),
+        )
+        .arg(
+            option("poll-interval", "POLL_INTERVAL_SECS")
+                .help("Seconds between checking for new jokes")
+                .validator(is_positive)
+                .default_value("30"),
+        )
+        .arg(
+            option("poll-jitter", "POLL_JITTER_SECS")
+                .help("Wait up to this many seconds more or less than POLL_INTERVAL_SECS between cycles")
+                .validator(is::<u64>)
+                .default_value("0"),
+        )
+        .arg(
+            option("display-hold", "DISPLAY_HOLD_SECS")
+                .help("Seconds to keep a joke on screen after it has been read out, before the next cycle starts")
+                .validator(is::<u64>)
+                .default_value("0"),
+        )
+        .arg(
+            option("batch-size", "BATCH_SIZE")
+                .help("How many new jokes to tell back to back in a cycle")
+                .validator(is_positive)
+                .default_value("1"),
+        )
+        .arg(
+            option("batch-pause", "BATCH_PAUSE_SECS")
+                .help("Seconds between the jokes of a batch")
+                .validator(is::<u64>)
+                .default_value("5"),
+        )
+        .arg(
+            option("max-backoff", "MAX_BACKOFF_SECS")
+                .help("Most seconds to wait between cycles when they keep failing")
+                .validator(is_positive)
+                .default_value("600"),
+        )
+        .arg(
+            option("http-timeout", "HTTP_TIMEOUT_SECS")
+                .help("Timeout in seconds of every request to reddit, the spacestate and Polly")
+                .validator(is::<u64>)
+                .default_value("10"),
+        )
+        .arg(
+            option("proxy-url", "PROXY_URL")
+                .help("Http proxy for the requests to reddit, the spacestate and Polly, instead of HTTPS_PROXY and HTTP_PROXY")
+                .validator(is_proxy_url),
+        )
+        .arg(
+            option("http-retries", "HTTP_RETRIES")
+                .help("How many times to try reaching reddit and the spacestate before skipping a cycle")
+                .validator(is::<u32>)
+                .default_value("3"),
+        )
+        .arg(
+            option("metrics-addr", "METRICS_ADDR")
+                .help("Address to serve Prometheus metrics on, empty to disable metrics"),
+        )
+        .arg(
+            option("health-addr", "HEALTH_ADDR")
+                .help("Address to serve the health check on, empty to disable the health check"),
+        )
+        .arg(
+            option("health-max-age", "HEALTH_MAX_AGE")
+                .help("Seconds without a successful cycle before the health check fails")
+                .validator(is_positive)
+                .default_value("600"),
+        )
+        .arg(
+            option("web-addr", "WEB_ADDR")
+                .help("Address to serve the dashboard on, empty to disable the dashboard"),
+        )
+        .arg(
+            option("webhook-url", "WEBHOOK_URL")
+                .help("Webhook to post every told joke to, empty to not post jokes"),
+        )
+        .arg(
+            option("webhook-format", "WEBHOOK_FORMAT")
+                .help("Chat service WEBHOOK_URL belongs to")
+                .possible_values(&["discord", "slack"])
+                .default_value("discord"),
+        )
+        .arg(
+            option("on-joke-cmd", "ON_JOKE_CMD")
+                .help("Shell command to run after every told joke, with the joke as json on stdin"),
+        )
+        .arg(
+            option("on-joke-timeout-secs", "ON_JOKE_TIMEOUT_SECS")
+                .help("Seconds after which ON_JOKE_CMD is killed")
+                .validator(is_positive)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only display jokes, without synthesizing or playing them [env: DRY_RUN]"),
+        )
+        .arg(
+            Arg::with_name("no-record")
+                .long("no-record")
+                .help("Don't save which jokes have been told [env: NO_RECORD]"),
+        )
+        .arg(
+            Arg::with_name("once").long("once").help(
+                "Run a single cycle and exit with 0 if a joke was told, 2 if not and 1 on errors \
+                 [env: RUN_ONCE]",
+            ),
+        )
+        .arg(
+            Arg::with_name("list-voices")
+                .long("list-voices")
+                .help("Print the Polly voices of POLLY_LANGUAGE_CODE with their gender and engines, and exit"),
+        )
+        .arg(
+            Arg::with_name("force-tty")
+                .long("force-tty")
+                .help("Draw the jokes on the terminal, even if stdout is not a terminal [env: FORCE_TTY]"),
+        )
+        .arg(
+            Arg::with_name("no-tty")
+                .long("no-tty")
+                .conflicts_with("force-tty")
+                .help("Log the jokes as plain text instead of drawing them, even if stdout is a terminal [env: NO_TTY]"),
+        )
+}
+
+fn option(name: &'static str, env: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name).long(name).env(env).takes_value(true)
+}
+
+/// The value of an argument that has a default value.
+pub fn value(name: &str) -> &'static str {
+    ARGS.value_of(name)
+        .unwrap_or_else(|| panic!("Missing argument {}", name))
+}
+
+/// The value of an optional argument, treating empty values as not set.
+pub fn optional(name: &str) -> Option<&'static str> {
+    ARGS.value_of(name).filter(|value| !value.is_empty())
+}
+
+/// The parsed value of an argument that has a default value.
+pub fn parse<T: FromStr>(name: &str) -> T {
+    value(name)
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid argument {}", name))
+}
+
+/// The parsed value of an optional argument.
+pub fn parse_optional<T: FromStr>(name: &str) -> Option<T> {
+    optional(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid argument {}", name))
+    })
+}
+
+/// The value of a boolean option.
+pub fn flag(name: &str) -> bool {
+    parse_bool(value(name)).unwrap_or_else(|| panic!("Invalid argument {}", name))
+}
+
+/// Whether the switch `name` was passed, or the environment variable `env` is set to true.
+pub fn switch(name: &str, env: &str) -> bool {
+    ARGS.is_present(name)
+        || std::env::var(env)
+            .ok()
+            .and_then(|value| parse_bool(&value))
+            .unwrap_or(false)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn is<T: FromStr>(value: String) -> Result<(), String> {
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|_| format!("{:?} is not a valid number", value))
+}
+
+fn is_optional<T: FromStr>(value: String) -> Result<(), String> {
+    if value.is_empty() {
+        Ok(())
+    } else {
+        is::<T>(value)
+    }
+}
+
+fn is_proxy_url(value: String) -> Result<(), String> {
+    match reqwest::Url::parse(&value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+        // reqwest is built without socks support
+        Ok(url) => Err(format!("{:?} proxies are not supported", url.scheme())),
+        Err(e) => Err(format!("{:?} is not a url: {}", value, e)),
+    }
+}
+
+fn is_speech_rate(value: String) -> Result<(), String> {
+    if value.is_empty() || tts::is_speech_rate(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} is not a rate like slow or a percentage from 20% to 200%",
+            value
+        ))
+    }
+}
+
+fn is_speech_pitch(value: String) -> Result<(), String> {
+    if value.is_empty() || tts::is_speech_pitch(&value) {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a pitch like low, +5% or -10%", value))
+    }
+}
+
+fn is_json_pointer(value: String) -> Result<(), String> {
+    if value.is_empty() || value.starts_with('/') {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} is not a json pointer like /state/open",
+            value
+        ))
+    }
+}
+
+fn is_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(()),
+        _ => Err(format!("{:?} is not a positive number", value)),
+    }
+}
+
+fn is_audio_format(value: String) -> Result<(), String> {
+    if value == "json" {
+        Err(String::from(
+            "json only returns speech marks, which can't be played",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_time(value: String) -> Result<(), String> {
+    if value.is_empty() || quiet_hours::parse_time(&value).is_ok() {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a time like 23:30", value))
+    }
+}
+
+fn is_bool(value: String) -> Result<(), String> {
+    parse_bool(&value)
+        .map(|_| ())
+        .ok_or_else(|| format!("{:?} is not \"true\" or \"false\"", value))
+}
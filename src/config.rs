@@ -0,0 +1,403 @@
+use crate::abbreviations::Abbreviations;
+use crate::audio::{self, AudioConfig};
+use crate::blocklist::Blocklist;
+use crate::cli;
+use crate::display::{self, DisplayConfig, RevealStyle};
+use crate::hook::HookConfig;
+use crate::http::HttpConfig;
+use crate::quiet_hours::{self, QuietHours, QuietTimezone};
+use crate::reddit::{
+    self, AnnouncementFilter, Listing, RedditConfig, RedditSource, SelectionMode, TimeRange,
+};
+use crate::spacestate::{SpacestateConfig, SpacestateFormat};
+use crate::storage::{UsedIdsBackend, UsedIdsConfig};
+use crate::tts::{self, LongTextMode, OutputFormat, PollyEngine, Prosody, TtsConfig, UrlMode};
+use crate::webhook::{WebhookConfig, WebhookFormat};
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+/// How often jokes are told while the space is open
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Tell a joke whenever there is a new one
+    Continuous,
+    /// Tell the highest scoring joke once per day
+    Daily,
+}
+
+/// All settings, read once at startup and passed to whatever needs them.
+pub struct Config {
+    pub spacestate: SpacestateConfig,
+    pub reddit: RedditConfig,
+    pub blocklist: Blocklist,
+    pub used_ids: UsedIdsConfig,
+    pub tts: TtsConfig,
+    pub abbreviations: Abbreviations,
+    pub audio: AudioConfig,
+    pub display: DisplayConfig,
+    pub quiet_hours: QuietHours,
+    pub http: HttpConfig,
+    pub webhook: WebhookConfig,
+    pub hook: HookConfig,
+    /// Where every told joke is added to, `None` to not keep a history
+    pub history_file: Option<&'static str>,
+    /// Where the audio of the told jokes is kept, `None` to not keep it
+    pub archive_dir: Option<&'static str>,
+    /// Print the available voices and exit
+    pub list_voices: bool,
+    pub mode: Mode,
+    /// How often to look for new voices, `None` to only load them at startup
+    pub voice_refresh: Option<Duration>,
+    /// How often to compact the used ids and history, `None` to only compact them at startup
+    pub compact_interval: Option<Duration>,
+    /// Seed of the random voice and joke picks, random itself when `None`
+    pub rng_seed: Option<u64>,
+    /// Show jokes without synthesizing or playing them
+    pub dry_run: bool,
+    /// Don't remember which jokes have been told
+    pub no_record: bool,
+    /// Run a single cycle and exit
+    pub run_once: bool,
+    pub poll_interval: Duration,
+    /// Most time to randomly add to or take from `poll_interval`
+    pub poll_jitter: Duration,
+    pub max_backoff: Duration,
+    /// How long a joke stays on screen at least after it has been read out
+    pub display_hold: Duration,
+    /// How many of the last told jokes are not told again, whatever the used ids say
+    pub recent_window: usize,
+    /// How many jokes to tell back to back in a cycle, when there are that many new ones
+    pub batch_size: u32,
+    /// The pause between the jokes of a batch
+    pub batch_pause: Duration,
+    /// The pause between the title and the selftext, both on screen and when reading them out.
+    /// `None` to show the joke at once and only pause for `tts::DEFAULT_PUNCHLINE_BREAK` with SSML
+    pub punchline_delay: Option<Duration>,
+    pub metrics_addr: Option<&'static str>,
+    pub web_addr: Option<&'static str>,
+    pub health_addr: Option<&'static str>,
+    /// Seconds without a successful cycle before the health check fails
+    pub health_max_age: u64,
+}
+
+/// Returned by `load_config` when one or more required settings are missing, or a file they point
+/// to can't be read.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The settings that are not set, with their environment variable and command line argument
+    missing: Vec<String>,
+    /// Why the files of the settings could not be read
+    unreadable: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.missing.is_empty() {
+            writeln!(f, "Missing required configuration:")?;
+            for missing in &self.missing {
+                writeln!(f, "  {}", missing)?;
+            }
+        }
+        for unreadable in &self.unreadable {
+            writeln!(f, "{}", unreadable)?;
+        }
+        write!(
+            f,
+            "Set these in the environment or in .env, see .env.example for an example"
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Read the settings, collecting every missing required one into a single error.
+pub fn load_config() -> Result<Config, ConfigError> {
+    let mut missing = Vec::new();
+    let mut required = |arg: &'static str, env: &'static str| {
+        let value = cli::optional(arg);
+        if value.is_none() {
+            missing.push(format!("{} (or --{})", env, arg));
+        }
+        value.unwrap_or_default()
+    };
+
+    let spacestate_url = required("spacestate-url", "SPACESTATE_URL");
+    let source = match cli::value("reddit-source") {
+        "file" => RedditSource::File(required("reddit-file", "REDDIT_FILE")),
+        _ => RedditSource::Urls(reddit_urls()),
+    };
+    let used_ids_backend = match cli::value("used-ids-backend") {
+        "sqlite" => UsedIdsBackend::Sqlite,
+        _ => UsedIdsBackend::File,
+    };
+    let used_ids_path = match used_ids_backend {
+        UsedIdsBackend::File => required("used-ids-file", "USED_IDS_FILE"),
+        UsedIdsBackend::Sqlite => required("used-ids-db", "USED_IDS_DB"),
+    };
+    let aws_polly_access_key = required("aws-polly-access-key", "AWS_POLLY_ACCESS_KEY");
+    let aws_polly_secret_access_key =
+        required("aws-polly-secret-access-key", "AWS_POLLY_SECRET_ACCESS_KEY");
+
+    if matches!(&source, RedditSource::Urls(urls) if urls.is_empty()) {
+        missing.push(String::from(
+            "REDDIT_URL or REDDIT_SUBREDDIT (or --reddit-url or --reddit-subreddit)",
+        ));
+    }
+
+    let mut unreadable = Vec::new();
+    let blocklist = match cli::optional("blocklist-file") {
+        Some(path) => readable(Blocklist::load(path), "BLOCKLIST_FILE", &mut unreadable),
+        None => Some(Blocklist::default()),
+    };
+    let abbreviations = match cli::optional("abbreviations-file") {
+        Some(path) => readable(
+            Abbreviations::load(path),
+            "ABBREVIATIONS_FILE",
+            &mut unreadable,
+        ),
+        None => Some(Abbreviations::default()),
+    };
+    let (blocklist, abbreviations) = match (blocklist, abbreviations) {
+        (Some(blocklist), Some(abbreviations)) if missing.is_empty() => (blocklist, abbreviations),
+        _ => {
+            return Err(ConfigError {
+                missing,
+                unreadable,
+            })
+        }
+    };
+
+    let intro_text = cli::optional("intro-text");
+    let spoken_urls = match cli::value("spoken-urls") {
+        "remove" => UrlMode::Remove,
+        "keep" => UrlMode::Keep,
+        _ => UrlMode::Link,
+    };
+    Ok(Config {
+        spacestate: SpacestateConfig {
+            url: spacestate_url,
+            format: match cli::value("spacestate-format") {
+                "spaceapi" => SpacestateFormat::SpaceApi,
+                _ => SpacestateFormat::Pixelbar,
+            },
+            custom: cli::optional("spacestate-json-pointer")
+                .map(|pointer| (pointer, cli::value("spacestate-open-value"))),
+            open_cycles_required: cli::parse("open-cycles-required"),
+        },
+        reddit: RedditConfig {
+            source,
+            user_agent: reddit::user_agent(cli::optional("reddit-username")),
+            selection_mode: match cli::value("selection-mode") {
+                "weighted" => SelectionMode::Weighted,
+                "trending" => SelectionMode::Trending,
+                _ => SelectionMode::Top,
+            },
+            selection_score_floor: cli::parse("selection-score-floor"),
+            trending_gravity: cli::parse("trending-gravity"),
+            min_score: cli::parse("min-score"),
+            min_comments: cli::parse_optional("min-comments"),
+            min_upvote_ratio: cli::parse_optional("min-upvote-ratio"),
+            skip_nsfw: cli::flag("skip-nsfw"),
+            skip_removed: cli::flag("skip-removed"),
+            require_selftext: cli::flag("require-selftext"),
+            use_top_comment: cli::flag("use-top-comment"),
+            announcement_filter: AnnouncementFilter {
+                title_phrases: lowercase_list("skip-title-phrases"),
+                authors: lowercase_list("skip-authors"),
+                flairs: lowercase_list("skip-flairs"),
+            },
+        },
+        blocklist,
+        used_ids: UsedIdsConfig {
+            backend: used_ids_backend,
+            path: used_ids_path,
+            ttl: cli::parse_optional("used-id-ttl-days")
+                .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+            max: cli::parse("max-used-ids"),
+            dedupe_by_title: cli::flag("dedupe-by-title"),
+        },
+        tts: TtsConfig {
+            aws_polly_access_key,
+            aws_polly_secret_access_key,
+            aws_region: cli::parse("aws-region"),
+            engine: match cli::value("polly-engine") {
+                "neural" => PollyEngine::Neural,
+                _ => PollyEngine::Standard,
+            },
+            language_code: cli::value("polly-language-code"),
+            voice_id: cli::optional("polly-voice-id"),
+            voice_gender: match cli::value("voice-gender") {
+                "any" => None,
+                gender => Some(gender),
+            },
+            // Validated by the parser of the arguments
+            voice_weights: cli::optional("voice-weights")
+                .map(|weights| tts::parse_voice_weights(weights).unwrap()),
+            output_format: match cli::value("output-format") {
+                "ogg_vorbis" => OutputFormat::OggVorbis,
+                "pcm" => OutputFormat::Pcm,
+                _ => OutputFormat::Mp3,
+            },
+            use_ssml: cli::flag("use-ssml"),
+            intro_text,
+            prosody: Prosody {
+                rate: cli::optional("speech-rate"),
+                pitch: cli::optional("speech-pitch"),
+            },
+            spoken_urls,
+            cache_audio: cli::flag("cache-audio"),
+            audio_cache_dir: cli::value("audio-cache-dir"),
+            stats_file: cli::value("polly-stats-file"),
+            cost_per_million: cli::parse("polly-cost-per-million"),
+            long_text_mode: match cli::value("long-text-mode") {
+                "chunk" => LongTextMode::Chunk,
+                _ => LongTextMode::Truncate,
+            },
+            max_text_chars: cli::parse("max-text-chars"),
+            retries: cli::parse("polly-retries"),
+            fallback: cli::flag("tts-fallback"),
+            fallback_command: cli::value("tts-fallback-command"),
+        },
+        abbreviations,
+        audio: AudioConfig {
+            volume: audio::playback_volume(cli::parse("playback-volume")),
+            device_name: cli::optional("audio-device-name"),
+            require_audio: cli::flag("require-audio"),
+            rimshot_file: cli::optional("rimshot-file"),
+        },
+        display: DisplayConfig {
+            is_tty: if cli::switch("no-tty", "NO_TTY") {
+                false
+            } else {
+                cli::switch("force-tty", "FORCE_TTY") || std::io::stdout().is_terminal()
+            },
+            use_colors: std::env::var_os("NO_COLOR").is_none()
+                && std::env::var("TERM").ok().as_deref() != Some("dumb"),
+            title_color: display::color(cli::value("title-color")),
+            text_color: display::color(cli::value("text-color")),
+            reveal_style: match cli::value("reveal-style") {
+                "typewriter" => RevealStyle::Typewriter,
+                _ => RevealStyle::Instant,
+            },
+            reveal_delay: Duration::from_millis(cli::parse("reveal-delay-ms")),
+            display_urls: cli::flag("display-urls"),
+            spoken_urls,
+            show_metadata: cli::flag("show-metadata"),
+            intro: intro_text.filter(|_| cli::flag("intro-display")),
+        },
+        quiet_hours: {
+            // Validated by the parser of the arguments
+            let time =
+                |name| cli::optional(name).map(|time| quiet_hours::parse_time(time).unwrap());
+            QuietHours::new(
+                time("quiet-start"),
+                time("quiet-end"),
+                match cli::value("quiet-timezone") {
+                    "utc" => QuietTimezone::Utc,
+                    _ => QuietTimezone::Local,
+                },
+                cli::flag("quiet-display"),
+            )
+        },
+        http: HttpConfig {
+            timeout: Duration::from_secs(cli::parse("http-timeout")),
+            retries: cli::parse("http-retries"),
+            proxy_url: cli::optional("proxy-url"),
+        },
+        webhook: WebhookConfig {
+            url: cli::optional("webhook-url"),
+            format: match cli::value("webhook-format") {
+                "slack" => WebhookFormat::Slack,
+                _ => WebhookFormat::Discord,
+            },
+        },
+        hook: HookConfig {
+            command: cli::optional("on-joke-cmd"),
+            timeout: Duration::from_secs(cli::parse("on-joke-timeout-secs")),
+        },
+        history_file: cli::optional("history-file"),
+        archive_dir: cli::optional("archive-dir"),
+        list_voices: cli::ARGS.is_present("list-voices"),
+        mode: match cli::value("mode") {
+            "daily" => Mode::Daily,
+            _ => Mode::Continuous,
+        },
+        voice_refresh: cli::parse_optional("voice-refresh-hours")
+            .map(|hours: u64| Duration::from_secs(hours * 60 * 60)),
+        compact_interval: cli::parse_optional("compact-interval-hours")
+            .map(|hours: u64| Duration::from_secs(hours * 60 * 60)),
+        rng_seed: cli::parse_optional("rng-seed"),
+        dry_run: cli::switch("dry-run", "DRY_RUN"),
+        no_record: cli::switch("no-record", "NO_RECORD"),
+        run_once: cli::switch("once", "RUN_ONCE"),
+        poll_interval: Duration::from_secs(cli::parse("poll-interval")),
+        poll_jitter: Duration::from_secs(cli::parse("poll-jitter")),
+        max_backoff: Duration::from_secs(cli::parse("max-backoff")),
+        display_hold: Duration::from_secs(cli::parse("display-hold")),
+        recent_window: cli::parse("recent-window"),
+        batch_size: cli::parse("batch-size"),
+        batch_pause: Duration::from_secs(cli::parse("batch-pause")),
+        punchline_delay: cli::parse_optional("punchline-delay-ms").map(Duration::from_millis),
+        metrics_addr: cli::optional("metrics-addr"),
+        web_addr: cli::optional("web-addr"),
+        health_addr: cli::optional("health-addr"),
+        health_max_age: cli::parse("health-max-age"),
+    })
+}
+
+/// The contents of the file of setting `env`, or `None` after adding why it can't be read to
+/// `unreadable`.
+fn readable<T>(
+    result: Result<T, anyhow::Error>,
+    env: &str,
+    unreadable: &mut Vec<String>,
+) -> Option<T> {
+    result
+        .map_err(|e| {
+            unreadable.push(format!("Could not read {}: {:#}", env, e));
+        })
+        .ok()
+}
+
+/// The comma separated items of argument `name`, in lowercase.
+fn lowercase_list(name: &str) -> Vec<String> {
+    cli::optional(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The listing urls in `REDDIT_URL`, or otherwise the urls built from `REDDIT_SUBREDDIT`.
+fn reddit_urls() -> Vec<String> {
+    let split = |list: &'static str| {
+        list.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+    };
+    if let Some(urls) = cli::optional("reddit-url") {
+        return split(urls).map(String::from).collect();
+    }
+    let listing = match cli::value("reddit-listing") {
+        "hot" => Listing::Hot,
+        "top" => Listing::Top,
+        _ => Listing::New,
+    };
+    let time = match cli::value("reddit-time") {
+        "hour" => TimeRange::Hour,
+        "week" => TimeRange::Week,
+        "month" => TimeRange::Month,
+        "year" => TimeRange::Year,
+        "all" => TimeRange::All,
+        _ => TimeRange::Day,
+    };
+    let limit = cli::parse("reddit-limit");
+    cli::optional("reddit-subreddit")
+        .map(split)
+        .into_iter()
+        .flatten()
+        .map(|subreddit| reddit::build_reddit_url(subreddit, listing, time, limit))
+        .collect()
+}
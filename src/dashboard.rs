@@ -0,0 +1,182 @@
+use crate::error::AppError;
+use crate::reddit::RedditPost;
+
+/// The state of the main loop, as shown on the dashboard
+#[cfg(feature = "dashboard")]
+#[derive(Default)]
+struct State {
+    /// The joke that is being told right now
+    current: Option<Joke>,
+    /// The last `RECENT_JOKES` jokes, most recent first
+    recent: std::collections::VecDeque<Joke>,
+    /// `None` until the spacestate has been checked
+    space_open: Option<bool>,
+}
+
+#[cfg(feature = "dashboard")]
+#[derive(Clone)]
+struct Joke {
+    title: String,
+    selftext: String,
+    told_at: String,
+}
+
+/// How many jokes are shown under "Recent jokes"
+#[cfg(feature = "dashboard")]
+const RECENT_JOKES: usize = 10;
+
+#[cfg(feature = "dashboard")]
+lazy_static::lazy_static! {
+    static ref STATE: std::sync::Mutex<State> = Default::default();
+}
+
+#[cfg(feature = "dashboard")]
+fn state() -> std::sync::MutexGuard<'static, State> {
+    // The state is only displayed, so a panic halfway through an update is harmless
+    STATE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Show `post` as the joke that is being told.
+#[cfg(feature = "dashboard")]
+pub fn joke_started(post: &RedditPost) {
+    let joke = Joke {
+        title: post.title.clone(),
+        selftext: post.selftext.clone(),
+        told_at: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+    };
+    let mut state = state();
+    state.recent.push_front(joke.clone());
+    state.recent.truncate(RECENT_JOKES);
+    state.current = Some(joke);
+}
+
+/// Stop showing a joke as being told, once it has been read out.
+#[cfg(feature = "dashboard")]
+pub fn joke_finished() {
+    state().current = None;
+}
+
+#[cfg(feature = "dashboard")]
+pub fn set_space_open(open: bool) {
+    state().space_open = Some(open);
+}
+
+/// The dashboard page, which reloads itself every few seconds.
+#[cfg(feature = "dashboard")]
+fn render() -> String {
+    let state = state();
+    let space = match state.space_open {
+        Some(true) => "open",
+        Some(false) => "closed",
+        None => "unknown",
+    };
+    let joke = |joke: &Joke| {
+        format!(
+            "<article><h3>{}</h3><p>{}</p><small>{}</small></article>",
+            escape_html(&joke.title),
+            escape_html(&joke.selftext).replace('\n', "<br>"),
+            joke.told_at
+        )
+    };
+    let current = match &state.current {
+        Some(current) => joke(current),
+        None => String::from("<p>Nothing</p>"),
+    };
+    let recent: String = state.recent.iter().map(joke).collect();
+    format!(
+        "<!DOCTYPE html>\
+         <html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"5\">\
+         <title>dadjokes</title></head><body>\
+         <p>The space is {}</p>\
+         <h2>Now telling</h2>{}\
+         <h2>Recent jokes</h2>{}\
+         </body></html>",
+        space, current, recent
+    )
+}
+
+#[cfg(feature = "dashboard")]
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The last `RECENT_JOKES` jokes in `HISTORY_FILE` at `path`, most recent first. Lines that
+/// aren't valid entries are skipped.
+#[cfg(feature = "dashboard")]
+fn load_recent(path: &str) -> std::io::Result<Vec<Joke>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let joke = |line: &str| {
+        let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+        let timestamp = entry.get("timestamp")?.as_str()?;
+        let told_at = chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+        Some(Joke {
+            title: entry.get("title")?.as_str()?.to_owned(),
+            selftext: entry.get("selftext")?.as_str()?.to_owned(),
+            told_at: told_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+        })
+    };
+    Ok(contents
+        .lines()
+        .rev()
+        .filter_map(joke)
+        .take(RECENT_JOKES)
+        .collect())
+}
+
+/// Serve the dashboard on `http://<addr>/` from a background thread, starting with the recent jokes
+/// in `history_file`, if it is set.
+#[cfg(feature = "dashboard")]
+pub fn serve(addr: &str, history_file: Option<&str>) -> Result<(), AppError> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| AppError::Config(format!("Could not listen on {:?}: {}", addr, e)))?;
+    if let Some(path) = history_file {
+        match load_recent(path) {
+            Ok(recent) => state().recent = recent.into(),
+            Err(e) => log::warn!("Could not read the recent jokes from HISTORY_FILE: {:?}", e),
+        }
+    }
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/" {
+                let header = "Content-Type: text/html; charset=utf-8"
+                    .parse::<tiny_http::Header>()
+                    .unwrap();
+                tiny_http::Response::from_string(render()).with_header(header)
+            } else {
+                tiny_http::Response::from_string("Not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                log::warn!("Could not respond to dashboard request: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "dashboard"))]
+pub fn joke_started(_post: &RedditPost) {}
+
+#[cfg(not(feature = "dashboard"))]
+pub fn joke_finished() {}
+
+#[cfg(not(feature = "dashboard"))]
+pub fn set_space_open(_open: bool) {}
+
+#[cfg(not(feature = "dashboard"))]
+pub fn serve(_addr: &str, _history_file: Option<&str>) -> Result<(), AppError> {
+    Err(AppError::Config(String::from(
+        "WEB_ADDR is set, but dadjokes was built without the dashboard feature",
+    )))
+}
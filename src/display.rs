@@ -0,0 +1,681 @@
+use crate::reddit::RedditPost;
+use crate::{audio, keys, shutdown, tts};
+use anyhow::Context;
+use crossterm::{Attribute, Color};
+use log::{info, warn};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How the jokes are shown
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayConfig {
+    /// Whether the jokes are drawn on the terminal. Otherwise, like under systemd or when piped to
+    /// a file, they are logged as plain text and nothing else is written to stdout.
+    pub is_tty: bool,
+    /// Terminals that can't render colors are told so by `NO_COLOR` or `TERM=dumb`
+    pub use_colors: bool,
+    /// `None` for the default color of the terminal
+    pub title_color: Option<Color>,
+    pub text_color: Option<Color>,
+    pub reveal_style: RevealStyle,
+    /// The time between two characters with the typewriter `reveal_style`
+    pub reveal_delay: Duration,
+    /// Show the urls instead of replacing them like `spoken_urls`
+    pub display_urls: bool,
+    pub spoken_urls: tts::UrlMode,
+    /// Show the subreddit and score below the joke
+    pub show_metadata: bool,
+    /// `INTRO_TEXT` above the title, `None` unless `INTRO_DISPLAY` is set
+    pub intro: Option<&'static str>,
+}
+
+/// How the selftext of a joke appears on the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevealStyle {
+    /// Show the whole selftext at once
+    Instant,
+    /// Type out the selftext one character at a time
+    Typewriter,
+}
+
+/// Terminals smaller than this only get the joke logged. Multiplexers like tmux can report a size
+/// of 0 while a pane is being split.
+const MIN_WIDTH: u16 = 10;
+const MIN_HEIGHT: u16 = 3;
+
+/// The names of the colors that can be used for `TITLE_COLOR` and `TEXT_COLOR`.
+pub const COLOR_NAMES: &[&str] = &[
+    "default",
+    "black",
+    "dark_grey",
+    "red",
+    "dark_red",
+    "green",
+    "dark_green",
+    "yellow",
+    "dark_yellow",
+    "blue",
+    "dark_blue",
+    "magenta",
+    "dark_magenta",
+    "cyan",
+    "dark_cyan",
+    "white",
+    "grey",
+];
+
+/// The color named `name`, one of `COLOR_NAMES`. `None` for the default color of the terminal.
+pub fn color(name: &str) -> Option<Color> {
+    match name {
+        "default" => None,
+        color => color.parse().ok(),
+    }
+}
+
+/// The hint drawn at the top of the screen while `keys` are read
+const KEY_HINT: &str = "Press r to replay";
+
+/// A joke on the screen, kept around so it can be drawn again when the terminal is resized or
+/// the joke is replayed.
+pub struct ShownJoke {
+    config: DisplayConfig,
+    post: RedditPost,
+    size: (u16, u16),
+    /// `None` if the joke was only displayed
+    recording: Option<audio::Recording>,
+}
+
+impl ShownJoke {
+    pub fn post(&self) -> &RedditPost {
+        &self.post
+    }
+
+    pub fn recording(&self) -> Option<&audio::Recording> {
+        self.recording.as_ref()
+    }
+
+    pub fn with_recording(self, recording: audio::Recording) -> ShownJoke {
+        ShownJoke {
+            recording: Some(recording),
+            ..self
+        }
+    }
+
+    /// Draw the whole joke again at once, e.g. when it is replayed.
+    pub fn redraw(&mut self, cursor: &crossterm::TerminalCursor, terminal: &crossterm::Terminal) {
+        if !self.config.is_tty {
+            log_joke(&self.config, &self.post);
+            return;
+        }
+        match draw(&self.config, cursor, terminal, &self.post, true) {
+            Ok(size) => self.size = size,
+            Err(e) => warn!("Could not redraw joke: {:#}", e),
+        }
+    }
+
+    /// Draw the joke again, centered for the new size, if the terminal was resized since it was
+    /// last drawn.
+    pub fn redraw_if_resized(
+        &mut self,
+        cursor: &crossterm::TerminalCursor,
+        terminal: &crossterm::Terminal,
+    ) {
+        if !self.config.is_tty || terminal.terminal_size() == self.size {
+            return;
+        }
+        match draw(&self.config, cursor, terminal, &self.post, true) {
+            Ok(size) => self.size = size,
+            Err(e) => warn!("Could not redraw joke: {:#}", e),
+        }
+    }
+}
+
+/// Clear the terminal and show the title of `post` in the center of it, then the selftext after
+/// `punchline_delay`. Without it, the typewriter `reveal_style` still pauses for
+/// `tts::DEFAULT_PUNCHLINE_BREAK` and the instant one doesn't pause.
+///
+/// This waits until the selftext is shown, with the typewriter `reveal_style` until it has been
+/// typed out. Without `is_tty` the joke is logged instead.
+pub async fn show_joke(
+    config: &DisplayConfig,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+    post: &RedditPost,
+    punchline_delay: Option<Duration>,
+) -> Result<ShownJoke, anyhow::Error> {
+    let size = if !config.is_tty {
+        log_joke(config, post);
+        (0, 0)
+    } else if post.selftext.is_empty() {
+        draw(config, cursor, terminal, post, true)?
+    } else {
+        let (width, height) = draw(config, cursor, terminal, post, false)?;
+        let pause = match (punchline_delay, config.reveal_style) {
+            (Some(delay), _) => delay,
+            (None, RevealStyle::Typewriter) => tts::DEFAULT_PUNCHLINE_BREAK,
+            (None, RevealStyle::Instant) => Duration::from_secs(0),
+        };
+        shutdown::sleep(pause).await;
+        match config.reveal_style {
+            RevealStyle::Instant => draw(config, cursor, terminal, post, true)?,
+            RevealStyle::Typewriter if fits(width, height) => {
+                let layout = layout(config, post, width, height);
+                for (y, line) in layout.rows.selftext.zip(&layout.selftext) {
+                    type_line(config, cursor, terminal, y, line).await?;
+                }
+                (width, height)
+            }
+            RevealStyle::Typewriter => (width, height),
+        }
+    };
+    Ok(ShownJoke {
+        config: *config,
+        post: post.clone(),
+        size,
+        recording: None,
+    })
+}
+
+/// Log `post` as plain text, for when there is no terminal to draw it on.
+fn log_joke(config: &DisplayConfig, post: &RedditPost) {
+    info!("Telling {:?}", displayed_text(config, &post.title));
+    let selftext = displayed_text(config, &post.selftext).replace("\r\n", "\n");
+    for line in selftext.split(&['\n', '\r'][..]).map(str::trim) {
+        if !line.is_empty() {
+            info!("  {}", line);
+        }
+    }
+}
+
+/// Draw the title of `post` centered on the screen, and the selftext below it if `with_selftext`
+/// is set. Returns the size of the terminal it was drawn for.
+fn draw(
+    config: &DisplayConfig,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+    post: &RedditPost,
+    with_selftext: bool,
+) -> Result<(u16, u16), anyhow::Error> {
+    let (width, height) = terminal.terminal_size();
+    if !fits(width, height) {
+        warn!(
+            "Terminal of {}x{} is too small to draw the joke on, logging it instead",
+            width, height
+        );
+        log_joke(config, post);
+        return Ok((width, height));
+    }
+    terminal
+        .clear(crossterm::ClearType::All)
+        .context("Could not clear screen")?;
+
+    let Layout {
+        title,
+        selftext,
+        footer,
+        rows,
+    } = layout(config, post, width, height);
+    {
+        let y = rows.title.start;
+        for (y, line) in rows.title.zip(&title) {
+            cursor.goto(0, y).context("Could not move cursor")?;
+            write_styled(
+                config,
+                terminal,
+                line,
+                config.title_color,
+                Some(Attribute::Bold),
+            )
+            .context("Could not write title")?;
+        }
+        if let Some(intro) = config.intro {
+            // On a single line with an empty line below it, if there is room above the title
+            let intro = wrap_and_center(intro, width).into_iter().next();
+            if let (Some(intro), Some(y)) = (intro, y.checked_sub(2)) {
+                cursor.goto(0, y).context("Could not move cursor")?;
+                write_styled(config, terminal, &intro, None, Some(Attribute::Dim))
+                    .context("Could not write intro")?;
+            }
+        }
+        // The top row, as long as that isn't where the intro goes
+        if keys::is_listening() && y >= 3 {
+            if let Some(hint) = wrap_and_center(KEY_HINT, width).into_iter().next() {
+                cursor.goto(0, 0).context("Could not move cursor")?;
+                write_styled(config, terminal, &hint, None, Some(Attribute::Dim))
+                    .context("Could not write key hint")?;
+            }
+        }
+    }
+    if with_selftext {
+        for (y, line) in rows.selftext.zip(&selftext) {
+            cursor.goto(0, y).context("Could not move cursor")?;
+            write_styled(config, terminal, line, config.text_color, None)
+                .context("Could not write selftext")?;
+        }
+    }
+    for (y, line) in rows.footer.zip(&footer) {
+        cursor.goto(0, y).context("Could not move cursor")?;
+        write_styled(config, terminal, line, None, Some(Attribute::Dim))
+            .context("Could not write metadata")?;
+    }
+
+    Ok((width, height))
+}
+
+/// Whether a terminal of `width` by `height` is big enough to draw a joke on.
+fn fits(width: u16, height: u16) -> bool {
+    width >= MIN_WIDTH && height >= MIN_HEIGHT
+}
+
+/// The lines of a joke, and the rows they are drawn on
+struct Layout {
+    title: Vec<String>,
+    selftext: Vec<String>,
+    /// The `show_metadata` footer
+    footer: Vec<String>,
+    rows: JokeRows,
+}
+
+/// Lay out `post` on a terminal of `width` by `height`.
+///
+/// The selftext is laid out even while it isn't shown yet, so the title doesn't move once it is.
+fn layout(config: &DisplayConfig, post: &RedditPost, width: u16, height: u16) -> Layout {
+    let title = wrap_and_center(&displayed_text(config, &post.title), width);
+    let selftext = wrap_selftext(&displayed_text(config, &post.selftext), width);
+    let footer = if config.show_metadata {
+        wrap_and_center(&metadata(post), width)
+    } else {
+        Vec::new()
+    };
+    let rows = joke_rows(height, title.len(), selftext.len(), footer.len());
+    Layout {
+        title,
+        selftext,
+        footer,
+        rows,
+    }
+}
+
+/// The rows a joke is drawn on, these never overlap
+#[derive(Debug, PartialEq)]
+struct JokeRows {
+    title: Range<u16>,
+    selftext: Range<u16>,
+    footer: Range<u16>,
+}
+
+/// The rows for `title_lines` lines of title ending in the middle of a terminal `height` rows
+/// high, `selftext_lines` lines of selftext below it with an empty line in between, and
+/// `footer_lines` lines of footer at the bottom.
+///
+/// The footer is placed first, above the last row that is kept free for the spinner. When the
+/// selftext doesn't fit below the middle the joke is moved up, and when it doesn't fit at all it
+/// is cut short. The title only loses lines when it doesn't fit by itself.
+fn joke_rows(
+    height: u16,
+    title_lines: usize,
+    selftext_lines: usize,
+    footer_lines: usize,
+) -> JokeRows {
+    let lines = |count: usize| u16::try_from(count).unwrap_or(u16::MAX);
+    let footer = if footer_lines == 0 {
+        height..height
+    } else {
+        let end = height.saturating_sub(1);
+        end.saturating_sub(lines(footer_lines))..end
+    };
+    let bottom = footer.start;
+    let title = lines(title_lines).min(bottom);
+    let selftext = lines(selftext_lines).min(bottom.saturating_sub(title).saturating_sub(1));
+    let gap = if selftext > 0 { 1 } else { 0 };
+    let title_start = (bottom / 2)
+        .saturating_sub(title)
+        .min(bottom - title - gap - selftext);
+    let selftext_start = title_start + title + gap;
+    JokeRows {
+        title: title_start..title_start + title,
+        selftext: selftext_start..selftext_start + selftext,
+        footer,
+    }
+}
+
+/// The footer of `post` for `SHOW_METADATA`, like `r/dadjokes · 4.2k points`.
+fn metadata(post: &RedditPost) -> String {
+    let points = match post.score.abs() {
+        1 => format!("{} point", post.score),
+        score if score < 1_000 => format!("{} points", post.score),
+        score if score < 1_000_000 => format!("{:.1}k points", post.score as f64 / 1e3),
+        _ => format!("{:.1}M points", post.score as f64 / 1e6),
+    };
+    if post.subreddit.is_empty() {
+        points
+    } else {
+        format!("r/{} \u{b7} {}", post.subreddit, points)
+    }
+}
+
+/// `text` with the urls replaced like they are read out, unless `display_urls` is set.
+fn displayed_text<'a>(config: &DisplayConfig, text: &'a str) -> Cow<'a, str> {
+    if config.display_urls {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(tts::strip_urls(text, config.spoken_urls))
+    }
+}
+
+/// The lines of `selftext` wrapped and centered in `width` columns, with a single empty line
+/// between paragraphs whatever line endings and number of blank lines reddit gave us.
+fn wrap_selftext(selftext: &str, width: u16) -> Vec<String> {
+    let selftext = selftext.replace("\r\n", "\n").replace('\r', "\n");
+    let mut lines = Vec::new();
+    let mut blank = false;
+    for line in selftext.split('\n').map(str::trim) {
+        if line.is_empty() {
+            blank = !lines.is_empty();
+            continue;
+        }
+        if blank {
+            lines.push(String::new());
+            blank = false;
+        }
+        lines.extend(wrap_and_center(line, width));
+    }
+    lines
+}
+
+/// An animated status line at the bottom of the screen, shown while waiting on something slow.
+///
+/// The spinner is drawn from a separate thread and cleared when it is dropped.
+pub struct Spinner {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Show `message` with a spinner, unless the jokes aren't drawn on the terminal.
+    pub fn start(config: &DisplayConfig, message: &str) -> Spinner {
+        const FRAMES: &[char] = &['|', '/', '-', '\\'];
+        let message = message.to_owned();
+        let (stop, stopped) = mpsc::channel();
+        if !config.is_tty {
+            return Spinner { stop, thread: None };
+        }
+        let thread = std::thread::spawn(move || {
+            let cursor = crossterm::cursor();
+            let terminal = crossterm::terminal();
+            for frame in FRAMES.iter().cycle() {
+                let (_, height) = terminal.terminal_size();
+                let _ = cursor.goto(0, height.saturating_sub(1));
+                let _ = terminal.write(format!("{} {}", frame, message));
+                match stopped.recv_timeout(Duration::from_millis(100)) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+            let _ = terminal.clear(crossterm::ClearType::CurrentLine);
+        });
+        Spinner {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Type out a centered `line` on row `y`, one character every `reveal_delay`.
+async fn type_line(
+    config: &DisplayConfig,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+    y: u16,
+    line: &str,
+) -> Result<(), anyhow::Error> {
+    let text = line.trim_start_matches(' ');
+    let x = (line.len() - text.len()) as u16;
+    for (x, c) in (x..).zip(text.chars()) {
+        if shutdown::is_requested() {
+            break;
+        }
+        cursor.goto(x, y).context("Could not move cursor")?;
+        write_styled(
+            config,
+            terminal,
+            c.encode_utf8(&mut [0; 4]),
+            config.text_color,
+            None,
+        )
+        .context("Could not write selftext")?;
+        std::io::stdout()
+            .flush()
+            .context("Could not write selftext")?;
+        shutdown::sleep(config.reveal_delay).await;
+    }
+    Ok(())
+}
+
+/// Write `text` in `color` and with `attribute`, falling back to plain text if the terminal doesn't support colors.
+fn write_styled(
+    config: &DisplayConfig,
+    terminal: &crossterm::Terminal,
+    text: &str,
+    color: Option<Color>,
+    attribute: Option<Attribute>,
+) -> crossterm::Result<usize> {
+    if !config.use_colors {
+        return terminal.write(text);
+    }
+    let mut styled = crossterm::style(text);
+    if let Some(color) = color {
+        styled = styled.with(color);
+    }
+    if let Some(attribute) = attribute {
+        styled = styled.attr(attribute);
+    }
+    terminal.write(styled)
+}
+
+/// Split `text` on word boundaries into lines that fit in `width` columns, and center each line by
+/// padding it with spaces.
+///
+/// Words that are wider than `width` are split over multiple lines.
+fn wrap_and_center(text: &str, width: u16) -> Vec<String> {
+    let width = usize::from(width);
+    if width == 0 {
+        return Vec::new();
+    }
+
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for word in text.split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+        for chunk in chars.chunks(width) {
+            if current_len > 0 && current_len + 1 + chunk.len() > width {
+                lines.push((std::mem::take(&mut current), current_len));
+                current_len = 0;
+            }
+            if current_len > 0 {
+                current.push(' ');
+                current_len += 1;
+            }
+            current.extend(chunk);
+            current_len += chunk.len();
+        }
+    }
+    if current_len > 0 {
+        lines.push((current, current_len));
+    }
+    if lines.is_empty() {
+        // keep empty lines so paragraphs stay separated
+        return vec![String::new()];
+    }
+
+    lines
+        .into_iter()
+        .map(|(line, len)| format!("{}{}", " ".repeat(center_offset(width, len)), line))
+        .collect()
+}
+
+/// The column at which text of `len` columns should start to be centered in `width` columns.
+///
+/// Text that is wider than `width` starts at column 0.
+fn center_offset(width: usize, len: usize) -> usize {
+    width.saturating_sub(len) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_and_center_wraps_long_title() {
+        assert_eq!(
+            wrap_and_center("Why did the scarecrow win an award", 12),
+            vec!["Why did the", " scarecrow", "win an award"]
+        );
+    }
+
+    #[test]
+    fn wrap_and_center_splits_long_word() {
+        assert_eq!(
+            wrap_and_center("a supercalifragilistic word", 8),
+            vec!["   a", "supercal", "ifragili", "  stic", "  word"]
+        );
+    }
+
+    #[test]
+    fn wrap_and_center_exact_fit() {
+        assert_eq!(wrap_and_center("exact fit", 9), vec!["exact fit"]);
+        assert_eq!(wrap_and_center("", 9), vec![""]);
+        assert!(wrap_and_center("anything", 0).is_empty());
+    }
+
+    #[test]
+    fn wrap_selftext_normalizes_line_endings() {
+        assert_eq!(
+            wrap_selftext("\r\none\r\ntwo\r\n\r\n\n\r\nthree\rfour\n\n", 10),
+            vec!["   one", "   two", "", "  three", "   four"]
+        );
+        assert!(wrap_selftext("\n \r\n", 10).is_empty());
+    }
+
+    #[test]
+    fn wrap_selftext_wraps_long_paragraphs() {
+        let lines = wrap_selftext("short\n\nthis paragraph is longer than the width", 12);
+        assert_eq!(
+            lines,
+            vec![
+                "   short",
+                "",
+                "    this",
+                "paragraph is",
+                "longer than",
+                " the width"
+            ]
+        );
+        assert!(lines.iter().all(|line| line.chars().count() <= 12));
+    }
+
+    #[test]
+    fn metadata_shortens_large_scores() {
+        let post = |subreddit: &str, score| RedditPost {
+            score,
+            subreddit: subreddit.to_owned(),
+            ..RedditPost::default()
+        };
+        assert_eq!(metadata(&post("dadjokes", 1)), "r/dadjokes \u{b7} 1 point");
+        assert_eq!(
+            metadata(&post("dadjokes", 999)),
+            "r/dadjokes \u{b7} 999 points"
+        );
+        assert_eq!(
+            metadata(&post("dadjokes", 4_210)),
+            "r/dadjokes \u{b7} 4.2k points"
+        );
+        assert_eq!(metadata(&post("", 1_250_000)), "1.2M points");
+    }
+
+    #[test]
+    fn joke_rows_center_the_joke() {
+        assert_eq!(
+            joke_rows(24, 2, 3, 0),
+            JokeRows {
+                title: 10..12,
+                selftext: 13..16,
+                footer: 24..24,
+            }
+        );
+        assert_eq!(
+            joke_rows(24, 2, 3, 1),
+            JokeRows {
+                title: 9..11,
+                selftext: 12..15,
+                footer: 22..23,
+            }
+        );
+    }
+
+    #[test]
+    fn joke_rows_never_overlap() {
+        let rows = joke_rows(6, 5, 10, 0);
+        assert_eq!(rows.title, 0..5);
+        assert!(rows.selftext.is_empty());
+        assert_eq!(
+            joke_rows(8, 2, 10, 1),
+            JokeRows {
+                title: 0..2,
+                selftext: 3..6,
+                footer: 6..7,
+            }
+        );
+
+        let before =
+            |a: &Range<u16>, b: &Range<u16>| a.is_empty() || b.is_empty() || a.end <= b.start;
+        for height in 0..30 {
+            for title in 0..8 {
+                for selftext in 0..8 {
+                    for footer in 0..3 {
+                        let rows = joke_rows(height, title, selftext, footer);
+                        assert!(before(&rows.title, &rows.selftext), "{:?}", rows);
+                        let text_end = rows.title.end.max(rows.selftext.end);
+                        assert!(text_end <= rows.footer.start, "{:?}", rows);
+                        assert!(rows.footer.end <= height, "{:?}", rows);
+                    }
+                }
+            }
+        }
+        assert_eq!(joke_rows(u16::MAX, 1, 100_000, 0).selftext.end, u16::MAX);
+    }
+
+    #[test]
+    fn fits_rejects_tiny_terminals() {
+        assert!(fits(80, 24));
+        assert!(!fits(0, 24));
+        assert!(!fits(80, 0));
+        assert!(!fits(MIN_WIDTH - 1, MIN_HEIGHT));
+    }
+
+    #[test]
+    fn center_offset_does_not_underflow() {
+        assert_eq!(center_offset(10, 40), 0);
+        assert_eq!(center_offset(10, 4), 3);
+
+        let text = "a".repeat(40);
+        let lines = wrap_and_center(&text, 10);
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.len() <= 10));
+    }
+}
@@ -0,0 +1,80 @@
+use thiserror::Error;
+
+/// What a cycle failed on, so the main loop can decide whether and how soon to try again.
+///
+/// The errors below these are `anyhow::Error`s with the context of what failed, which are kept as
+/// the source and shown after the message with `{:#}`. Anything that is not categorized yet is
+/// treated like a remote error.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("Could not load posts from reddit")]
+    Reddit(#[source] anyhow::Error),
+    #[error("Could not get the spacestate")]
+    Spacestate(#[source] anyhow::Error),
+    #[error("Could not synthesize speech")]
+    Polly(#[source] anyhow::Error),
+    #[error("Could not play audio")]
+    Audio(#[source] anyhow::Error),
+    #[error("Could not read or write the used ids")]
+    Io(#[source] anyhow::Error),
+    /// Retrying doesn't help, the configuration has to be fixed
+    #[error("{0}")]
+    Config(String),
+}
+
+impl AppError {
+    /// Whether the error came from a service that may be overloaded or down, which is given more
+    /// time with every failure.
+    fn is_remote(&self) -> bool {
+        match self {
+            AppError::Reddit(_) | AppError::Spacestate(_) | AppError::Polly(_) => true,
+            AppError::Audio(_) | AppError::Io(_) | AppError::Config(_) => false,
+        }
+    }
+}
+
+/// What the main loop does after a failed cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    /// Wait longer with every failure in a row, up to `MAX_BACKOFF_SECS`
+    Backoff,
+    /// Try again after the normal `POLL_INTERVAL_SECS`, like a missing audio device that may be
+    /// plugged in again
+    Retry,
+    /// Stop, trying again would fail the same way
+    Exit,
+}
+
+/// How to recover from the failed cycle `e`.
+pub fn recovery(e: &anyhow::Error) -> Recovery {
+    match e.downcast_ref::<AppError>() {
+        Some(AppError::Config(_)) => Recovery::Exit,
+        Some(e) if !e.is_remote() => Recovery::Retry,
+        _ => Recovery::Backoff,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_depends_on_the_category() {
+        let inner = || anyhow::anyhow!("connection refused");
+        assert_eq!(
+            recovery(&AppError::Reddit(inner()).into()),
+            Recovery::Backoff
+        );
+        assert_eq!(
+            recovery(&AppError::Polly(inner()).into()),
+            Recovery::Backoff
+        );
+        assert_eq!(recovery(&AppError::Audio(inner()).into()), Recovery::Retry);
+        assert_eq!(recovery(&AppError::Io(inner()).into()), Recovery::Retry);
+        assert_eq!(
+            recovery(&AppError::Config(String::from("unknown voice")).into()),
+            Recovery::Exit
+        );
+        assert_eq!(recovery(&inner()), Recovery::Backoff);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::AppError;
+use std::sync::atomic::{AtomicI64, AtomicU8, Ordering};
+
+/// Unix timestamp of when the program started
+static STARTED_AT: AtomicI64 = AtomicI64::new(0);
+/// Unix timestamp of the last cycle that finished without an error, 0 if there was none yet
+static LAST_CYCLE_AT: AtomicI64 = AtomicI64::new(0);
+/// One of `POLLY_UNKNOWN`, `POLLY_OK` or `POLLY_FAILED`
+static LAST_POLLY_RESULT: AtomicU8 = AtomicU8::new(POLLY_UNKNOWN);
+
+const POLLY_UNKNOWN: u8 = 0;
+const POLLY_OK: u8 = 1;
+const POLLY_FAILED: u8 = 2;
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Mark the start of the program, which is when the uptime starts counting.
+pub fn started() {
+    STARTED_AT.store(now(), Ordering::Relaxed);
+}
+
+pub fn cycle_succeeded() {
+    LAST_CYCLE_AT.store(now(), Ordering::Relaxed);
+}
+
+pub fn polly_called(succeeded: bool) {
+    let result = if succeeded { POLLY_OK } else { POLLY_FAILED };
+    LAST_POLLY_RESULT.store(result, Ordering::Relaxed);
+}
+
+/// Whether a cycle succeeded in the last `max_age` seconds, or the program started less than
+/// `max_age` seconds ago.
+#[cfg(feature = "health")]
+fn is_healthy(max_age: i64) -> bool {
+    let last_activity = LAST_CYCLE_AT
+        .load(Ordering::Relaxed)
+        .max(STARTED_AT.load(Ordering::Relaxed));
+    now() - last_activity <= max_age
+}
+
+#[cfg(feature = "health")]
+fn render() -> String {
+    let last_cycle_at = match LAST_CYCLE_AT.load(Ordering::Relaxed) {
+        0 => None,
+        at => Some(at),
+    };
+    let polly_ok = match LAST_POLLY_RESULT.load(Ordering::Relaxed) {
+        POLLY_OK => Some(true),
+        POLLY_FAILED => Some(false),
+        _ => None,
+    };
+    serde_json::json!({
+        "uptime_secs": now() - STARTED_AT.load(Ordering::Relaxed),
+        "last_cycle_at": last_cycle_at,
+        "polly_ok": polly_ok,
+    })
+    .to_string()
+}
+
+/// Serve the health on `http://<addr>/health` from a background thread.
+///
+/// Responds with 503 instead of 200 when no cycle succeeded in the last `max_age` seconds, so the
+/// program can be restarted when the main loop is stuck.
+#[cfg(feature = "health")]
+pub fn serve(addr: &str, max_age: u64) -> Result<(), AppError> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| AppError::Config(format!("Could not listen on {:?}: {}", addr, e)))?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/health" {
+                let header = "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .unwrap();
+                let status = if is_healthy(max_age as i64) { 200 } else { 503 };
+                tiny_http::Response::from_string(render())
+                    .with_header(header)
+                    .with_status_code(status)
+            } else {
+                tiny_http::Response::from_string("Not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                log::warn!("Could not respond to health request: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "health"))]
+pub fn serve(_addr: &str, _max_age: u64) -> Result<(), AppError> {
+    Err(AppError::Config(String::from(
+        "HEALTH_ADDR is set, but dadjokes was built without the health feature",
+    )))
+}
@@ -0,0 +1,163 @@
+use crate::clock::{Clock, SystemClock};
+use crate::reddit::RedditPost;
+use crate::storage;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::Duration;
+
+/// A line in `HISTORY_FILE`
+#[derive(Serialize)]
+struct HistoryEntry<'a> {
+    /// When the joke was told, in RFC 3339
+    timestamp: String,
+    id: &'a str,
+    title: &'a str,
+    selftext: &'a str,
+    score: i64,
+    /// The Polly voice, `None` if the joke was only displayed or read by `TTS_FALLBACK_COMMAND`
+    voice: Option<&'a str>,
+    subreddit: &'a str,
+}
+
+/// Check that `HISTORY_FILE` can be written, if it is set.
+pub fn check_writable(history_file: Option<&str>) -> Result<(), anyhow::Error> {
+    match history_file {
+        Some(path) => storage::ensure_file_writable(path),
+        None => Ok(()),
+    }
+}
+
+/// Append `post` to `HISTORY_FILE`.
+///
+/// Does nothing if `HISTORY_FILE` is not set. Errors are only logged, the history is not needed to
+/// tell jokes.
+pub fn record(history_file: Option<&str>, post: &RedditPost, voice: Option<&str>) {
+    if let Some(path) = history_file {
+        let entry = HistoryEntry {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            id: &post.id,
+            title: &post.title,
+            selftext: &post.selftext,
+            score: post.score,
+            voice,
+            subreddit: &post.subreddit,
+        };
+        if let Err(e) = append(path, &entry) {
+            warn!("Could not add joke {:?} to the history: {:#}", post.id, e);
+        }
+    }
+}
+
+/// Drop the entries in `HISTORY_FILE` older than `ttl`, and all but the last `max` if that isn't 0.
+/// Returns how many entries were dropped.
+///
+/// Does nothing if `HISTORY_FILE` is not set or doesn't exist yet.
+pub fn compact(
+    history_file: Option<&str>,
+    ttl: Option<Duration>,
+    max: usize,
+) -> Result<usize, anyhow::Error> {
+    let path = match history_file {
+        Some(path) => path,
+        None => return Ok(0),
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => Err(e).context("Could not read HISTORY_FILE")?,
+    };
+    let (kept, pruned) = compact_lines(&contents, ttl, max, SystemClock.now());
+    if pruned > 0 {
+        storage::replace_file(path, kept.as_bytes()).context("Could not compact HISTORY_FILE")?;
+    }
+    Ok(pruned)
+}
+
+/// The lines of `contents` that were told within `ttl` before `now`, and at most the last `max`
+/// of them if `max` isn't 0. Returns them with how many lines were dropped.
+///
+/// Lines without a valid timestamp are only dropped to stay within `max`.
+fn compact_lines(
+    contents: &str,
+    ttl: Option<Duration>,
+    max: usize,
+    now: DateTime<Utc>,
+) -> (String, usize) {
+    let ttl = ttl.and_then(|ttl| chrono::Duration::from_std(ttl).ok());
+    let expired = |line: &str| {
+        let timestamp = serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|entry| {
+                let timestamp = entry.get("timestamp")?.as_str()?;
+                DateTime::parse_from_rfc3339(timestamp)
+                    .ok()
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+            });
+        match (ttl, timestamp) {
+            (Some(ttl), Some(timestamp)) => timestamp + ttl <= now,
+            _ => false,
+        }
+    };
+    let total = contents.lines().count();
+    let mut lines: Vec<&str> = contents.lines().filter(|line| !expired(line)).collect();
+    if max > 0 && lines.len() > max {
+        lines.drain(..lines.len() - max);
+    }
+    let pruned = total - lines.len();
+    let mut kept = String::new();
+    for line in lines {
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    (kept, pruned)
+}
+
+fn append(path: &str, entry: &HistoryEntry) -> Result<(), anyhow::Error> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Could not open HISTORY_FILE")?
+        .write_all(line.as_bytes())
+        .context("Could not write HISTORY_FILE")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn compact_lines_drops_expired_and_oldest_entries() {
+        let now = Utc.ymd(2019, 6, 10).and_hms(12, 0, 0);
+        let contents = "\
+{\"timestamp\":\"2019-06-01T12:00:00+02:00\",\"id\":\"expired\"}
+not json
+{\"timestamp\":\"2019-06-08T12:00:00+02:00\",\"id\":\"old\"}
+{\"timestamp\":\"2019-06-09T12:00:00+02:00\",\"id\":\"new\"}
+";
+        let week = Some(Duration::from_secs(7 * 24 * 60 * 60));
+        let (kept, pruned) = compact_lines(contents, week, 0, now);
+        assert_eq!(pruned, 1);
+        assert!(kept.starts_with("not json\n"));
+
+        let (kept, pruned) = compact_lines(contents, week, 1, now);
+        assert_eq!(pruned, 3);
+        assert_eq!(
+            kept,
+            "{\"timestamp\":\"2019-06-09T12:00:00+02:00\",\"id\":\"new\"}\n"
+        );
+
+        assert_eq!(
+            compact_lines(contents, None, 0, now),
+            (contents.to_owned(), 0)
+        );
+    }
+}
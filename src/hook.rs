@@ -0,0 +1,118 @@
+use crate::reddit::RedditPost;
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// The command that is run for every told joke
+pub struct HookConfig {
+    /// `ON_JOKE_CMD`, `None` to not run anything
+    pub command: Option<&'static str>,
+    /// How long the command may take before it is killed
+    pub timeout: Duration,
+}
+
+/// Run `ON_JOKE_CMD` in the background with `post` as json on stdin, so anything can be hooked up
+/// to the jokes without changing the bot. The command is run by the shell, so it can have
+/// arguments.
+///
+/// `voice` is the Polly voice the joke was read by, `None` if it was only displayed or read by
+/// the fallback. Does nothing if `ON_JOKE_CMD` is not set. Errors are only logged, and the command
+/// is killed after `ON_JOKE_TIMEOUT_SECS`.
+pub fn joke_told(config: &HookConfig, post: &RedditPost, voice: Option<&str>) {
+    if let Some(command) = config.command {
+        let event = event(post, voice);
+        let id = post.id.clone();
+        let timeout = config.timeout;
+        tokio::spawn(async move {
+            match tokio::time::timeout(timeout, run(command, &event)).await {
+                Ok(Ok(())) => debug!("Ran ON_JOKE_CMD for joke {:?}", id),
+                Ok(Err(e)) => warn!("ON_JOKE_CMD failed for joke {:?}: {:#}", id, e),
+                Err(_) => warn!(
+                    "ON_JOKE_CMD did not finish within {:?} for joke {:?}, killed it",
+                    timeout, id
+                ),
+            }
+        });
+    }
+}
+
+/// The json written to the stdin of `ON_JOKE_CMD`.
+fn event(post: &RedditPost, voice: Option<&str>) -> Value {
+    json!({
+        "id": post.id,
+        "title": post.title,
+        "selftext": post.selftext,
+        "voice": voice,
+        "subreddit": post.subreddit,
+        "score": post.score,
+        "permalink": post.permalink,
+    })
+}
+
+/// `command` as run by the shell.
+fn shell(command: &str) -> tokio::process::Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+async fn run(command: &str, event: &Value) -> Result<(), anyhow::Error> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        // Anything written to the terminal would end up on top of the joke
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Could not run {}", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut line = event.to_string();
+        line.push('\n');
+        // The command may not read stdin at all, which isn't an error
+        let _ = stdin.write_all(line.as_bytes()).await;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .with_context(|| format!("Could not wait for {}", command))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_passes_arguments_and_the_event() {
+        let path = std::env::temp_dir().join(format!("dadjokes-hook-{}.json", std::process::id()));
+        let event = json!({ "id": "abc" });
+        run(&format!("cat > '{}'", path.display()), &event)
+            .await
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "{\"id\":\"abc\"}\n");
+
+        let error = run("echo nope >&2; exit 3", &event).await.unwrap_err();
+        assert!(error.to_string().ends_with("nope"), "{}", error);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{proxy, shutdown};
+use anyhow::bail;
+use log::warn;
+use std::future::Future;
+use std::time::Duration;
+
+/// The settings shared by every request
+pub struct HttpConfig {
+    pub timeout: Duration,
+    /// How many times a failed request is tried, including the first time
+    pub retries: u32,
+    /// `PROXY_URL`, `None` to use the proxy in the standard environment variables, if any
+    pub proxy_url: Option<&'static str>,
+}
+
+/// The http client shared by every request, with the timeout and retries of `HttpConfig`.
+///
+/// Cloning it is cheap, the clones share the connections of the client.
+#[derive(Clone)]
+pub struct Http {
+    pub client: reqwest::Client,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Http {
+    pub fn new(config: &HttpConfig) -> Http {
+        let mut builder = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .connect_timeout(config.timeout);
+        if let Some(proxy) = proxy::configured_proxy(config.proxy_url) {
+            builder = builder.proxy(proxy);
+        }
+        Http {
+            client: builder.build().expect("Could not make http client"),
+            timeout: config.timeout,
+            retries: config.retries,
+        }
+    }
+
+    /// Call `f` until it succeeds, up to `HTTP_RETRIES` times, doubling the delay between
+    /// attempts.
+    pub async fn with_retries<T, E, F, Fut>(&self, description: &str, mut f: F) -> Result<T, E>
+    where
+        E: std::fmt::Debug,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.retries && !shutdown::is_requested() => {
+                    warn!(
+                        "Could not {} (attempt {}/{}), retrying in {:?}: {:?}",
+                        description, attempt, self.retries, delay, e
+                    );
+                    shutdown::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Fail if `future` takes longer than `HTTP_TIMEOUT`.
+    pub async fn with_timeout<T, E>(
+        &self,
+        future: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, anyhow::Error>
+    where
+        E: Into<anyhow::Error>,
+    {
+        match tokio::time::timeout(self.timeout, future).await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => bail!("Timed out after {:?}", self.timeout),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::shutdown;
+use crossterm::{InputEvent, KeyEvent};
+use log::warn;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set while the keys are read, so the hint for them is only drawn when they work.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+/// Set when `r` is pressed, until the replay is picked up by the main loop.
+static REPLAY: AtomicBool = AtomicBool::new(false);
+
+/// Read key presses in the background, if the jokes are drawn on a terminal (`is_tty`) the keys
+/// can come from.
+///
+/// Keys are read without waiting for an enter and aren't echoed on top of the joke, see
+/// `read_keys_directly`.
+pub fn listen(is_tty: bool) {
+    if !is_tty || !std::io::stdin().is_terminal() {
+        return;
+    }
+    if let Err(e) = read_keys_directly() {
+        warn!(
+            "Could not read keys, the last joke can't be replayed: {:?}",
+            e
+        );
+        return;
+    }
+    let mut events = crossterm::input().read_async();
+    LISTENING.store(true, Ordering::SeqCst);
+    std::thread::spawn(move || loop {
+        // Ends once the queued events are read, the reader never blocks
+        for event in events.by_ref() {
+            match event {
+                InputEvent::Keyboard(KeyEvent::Char('r')) => REPLAY.store(true, Ordering::SeqCst),
+                // Only in raw mode, otherwise Ctrl-C still sends `SIGINT`
+                InputEvent::Keyboard(KeyEvent::Ctrl('c')) => shutdown::request(),
+                _ => {}
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    });
+}
+
+/// Restore the terminal settings changed by `listen`.
+pub fn stop() {
+    if LISTENING.swap(false, Ordering::SeqCst) {
+        if let Err(e) = read_keys_normally() {
+            warn!("Could not restore the terminal settings: {:?}", e);
+        }
+    }
+}
+
+/// The settings of the terminal from before `read_keys_directly`
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: std::sync::Mutex<Option<libc::termios>> = std::sync::Mutex::new(None);
+
+/// Turn off line buffering and echo on the terminal the keys are read from.
+///
+/// Unlike the raw mode of crossterm, the output still translates `\n` to `\r\n`, so the log lines
+/// on stderr don't stair-step, and Ctrl-C still sends `SIGINT`.
+#[cfg(unix)]
+fn read_keys_directly() -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // The keys are read from /dev/tty, like crossterm does
+    let tty = std::fs::File::open("/dev/tty")?;
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let original = termios;
+    termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+    termios.c_cc[libc::VMIN] = 1;
+    termios.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    *ORIGINAL_TERMIOS.lock().unwrap() = Some(original);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_keys_normally() -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let original = match ORIGINAL_TERMIOS.lock().unwrap().take() {
+        Some(original) => original,
+        None => return Ok(()),
+    };
+    let tty = std::fs::File::open("/dev/tty")?;
+    if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &original) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raw mode of the Windows console leaves the output alone, so it is used outside of unix.
+#[cfg(not(unix))]
+fn read_keys_directly() -> io::Result<()> {
+    // Raw mode is left by `stop`, the process exits without dropping anything
+    crossterm::RawScreen::into_raw_mode()?.disable_drop();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn read_keys_normally() -> io::Result<()> {
+    crossterm::RawScreen::disable_raw_mode()
+}
+
+/// Whether keys are read, and the hint for them is shown.
+pub fn is_listening() -> bool {
+    LISTENING.load(Ordering::SeqCst)
+}
+
+/// Whether `r` was pressed since the last call.
+pub fn replay_requested() -> bool {
+    REPLAY.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,36 @@
+use crate::cli;
+use serde_json::json;
+use std::cell::RefCell;
+use std::io::Write;
+
+thread_local! {
+    /// The id of the joke that is currently being told, added to every json log line
+    static JOKE_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Set up the logger in the format configured by `LOG_FORMAT`.
+pub fn init() {
+    let mut builder = env_logger::Builder::from_default_env();
+    if cli::value("log-format") == "json" {
+        builder.format(|buf, record| {
+            let mut line = json!({
+                "ts": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+            });
+            JOKE_ID.with(|id| {
+                if let Some(id) = &*id.borrow() {
+                    line["joke_id"] = json!(id);
+                }
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
+
+/// Set the id of the joke that is being told, or `None` once it's done.
+pub fn set_joke_id(id: Option<&str>) {
+    JOKE_ID.with(|current| *current.borrow_mut() = id.map(str::to_owned));
+}
@@ -1,208 +1,731 @@
-use failure::{bail, ResultExt};
-use lazy_static::lazy_static;
-use log::{error, info};
-use rand::seq::SliceRandom;
-use rodio::source::Source;
-use rusoto_core::{HttpClient, Region};
-use rusoto_credential::StaticProvider;
-use rusoto_polly::{Polly, PollyClient};
-use serde_json::Value;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Cursor, Write};
-
-lazy_static! {
-    static ref SPACESTATE_URL: String =
-        std::env::var("SPACESTATE_URL").expect("Missing environment variable SPACESTATE_URL");
-    static ref REDDIT_URL: String =
-        std::env::var("REDDIT_URL").expect("Missing environment variable REDDIT_URL");
-    static ref USED_IDS_FILE: String =
-        std::env::var("USED_IDS_FILE").expect("Missing environment variable USED_IDS_FILE");
-    static ref AWS_POLLY_ACCESS_KEY: String = std::env::var("AWS_POLLY_ACCESS_KEY")
-        .expect("Missing environment variable AWS_POLLY_ACCESS_KEY");
-    static ref AWS_POLLY_SECRET_ACCESS_KEY: String = std::env::var("AWS_POLLY_SECRET_ACCESS_KEY")
-        .expect("Missing environment variable AWS_POLLY_SECRET_ACCESS_KEY");
-}
+mod abbreviations;
+mod archive;
+mod audio;
+mod blocklist;
+mod cli;
+mod clock;
+mod config;
+mod dashboard;
+mod display;
+mod error;
+mod health;
+mod history;
+mod hook;
+mod http;
+mod keys;
+mod logging;
+mod metrics;
+mod proxy;
+mod quiet_hours;
+mod reddit;
+mod shutdown;
+mod spacestate;
+mod storage;
+mod tts;
+mod webhook;
+
+use anyhow::{anyhow, bail};
+use clock::SystemClock;
+use config::{Config, Mode};
+use error::AppError;
+use http::Http;
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+use rusoto_polly::PollyClient;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use storage::UsedJokes;
+
+/// Toggled by `SIGUSR1`, no jokes are told while it is set.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Set once it's logged that every post has been told, until a new post shows up.
+static ALL_TOLD_LOGGED: AtomicBool = AtomicBool::new(false);
 
-fn main() {
-    dotenv::dotenv().expect("Could not read .env, have you copied .env.example?");
+/// Exit code of `--once` when no joke was told, e.g. because the space is closed
+const EXIT_NO_JOKE: i32 = 2;
 
-    env_logger::init();
+#[tokio::main]
+async fn main() {
+    health::started();
+    // The configuration can also come from the real environment, so a missing .env is fine
+    if let Err(e) = dotenv::dotenv() {
+        if !e.not_found() {
+            eprintln!("Could not read .env: {}", e);
+            std::process::exit(1);
+        }
+    }
+    lazy_static::initialize(&cli::ARGS);
+    // Before the configuration, so its warnings are logged
+    logging::init();
+    let config = match config::load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let http = Http::new(&config.http);
+    if config.list_voices {
+        let client = tts::new_client(&config.tts, &http, config.http.proxy_url);
+        match tts::describe_voices(&config.tts, &http, &client).await {
+            Ok(voices) => {
+                print!("{}", tts::format_voices(&voices));
+                std::process::exit(0);
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let writable = storage::ensure_file_writable(config.used_ids.path)
+        .and_then(|()| history::check_writable(config.history_file))
+        .and_then(|()| archive::check_writable(config.archive_dir))
+        .and_then(|()| tts::check_writable(&config.tts));
+    if let Err(e) = writable {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+    // Ctrl-C, SIGTERM and SIGHUP all stop the main loop, which restores the terminal on its way out
+    ctrlc::set_handler(shutdown::request).expect("Could not set Ctrl-C handler");
+    if std::process::id() == 1 {
+        // Reaping orphans ourselves would race the reaping of tokio for the espeak processes
+        info!("Running as PID 1, run the container with --init to reap orphaned processes");
+    }
+    if let Some(addr) = config.metrics_addr {
+        match metrics::serve(addr) {
+            Ok(()) => info!("Serving metrics on http://{}/metrics", addr),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(addr) = config.health_addr {
+        match health::serve(addr, config.health_max_age) {
+            Ok(()) => info!("Serving health on http://{}/health", addr),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(addr) = config.web_addr {
+        match dashboard::serve(addr, config.history_file) {
+            Ok(()) => info!("Serving dashboard on http://{}/", addr),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match proxy::proxy_in_use(config.http.proxy_url) {
+        Some(proxy) => info!("Sending requests through proxy {}", proxy),
+        None => debug!("Not using a proxy"),
+    }
+    let mut broadcasted_dadjokes = match storage::open(&config.used_ids) {
+        Ok(used_jokes) => used_jokes,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    compact(&config, &mut *broadcasted_dadjokes);
+    let mut compacted_at = Instant::now();
+    let mut recent_jokes = storage::RecentJokes::new(config.recent_window);
+    let client = tts::new_client(&config.tts, &http, config.http.proxy_url);
+    let mut voices = match load_voices(&config, &http, &client).await {
+        Ok(voices) => voices,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    listen_for_pause();
+    // Everything that can fail at startup is done, so those failures exit with the terminal untouched
+    keys::listen(config.display.is_tty);
     let cursor = crossterm::cursor();
     let terminal = crossterm::terminal();
-    cursor.hide().expect("Could not hide cursor");
+    if config.display.is_tty {
+        cursor.hide().expect("Could not hide cursor");
+        terminal
+            .clear(crossterm::ClearType::All)
+            .expect("Could not clear terminal");
+    } else {
+        info!("stdout is not a terminal, logging the jokes instead of drawing them");
+    }
+    let mut voices_loaded_at = Instant::now();
+    // Seeded, the voices and jokes are picked the same way in every run with the same posts
+    let mut rand = match config.rng_seed {
+        Some(seed) => {
+            info!("Picking voices and jokes with seed {}", seed);
+            StdRng::seed_from_u64(seed)
+        }
+        None => StdRng::from_entropy(),
+    };
+    info!("Using AWS region {}", config.tts.aws_region.name());
+
+    // `None` until the first lookup, so a missing device is also reported at startup
+    let mut device_name: Option<Option<String>> = None;
+    // Every joke is played on the same sink until the device changes, instead of opening a new
+    // stream on the device for every joke
+    let mut sink: Option<rodio::Sink> = None;
+    let mut exit_code = 0;
+    let mut open_streak = spacestate::OpenStreak::new(config.spacestate.open_cycles_required);
+    let mut shown: Option<display::ShownJoke> = None;
+    let mut failures = 0;
+    while !shutdown::is_requested() {
+        // Look for the device every cycle, so playback resumes once it is plugged back in
+        let device = audio::select_output_device(config.audio.device_name);
+        let name = device.as_ref().map(rodio::Device::name);
+        if device_name.as_ref() != Some(&name) {
+            sink = device
+                .as_ref()
+                .map(|device| audio::new_sink(device, config.audio.volume));
+            match &name {
+                Some(name) => info!("Playing audio on {:?}", name),
+                None if config.audio.require_audio => {
+                    error!("No audio device available and REQUIRE_AUDIO is set");
+                    exit_code = 1;
+                    break;
+                }
+                None => warn!("No audio device available, only displaying jokes"),
+            }
+            device_name = Some(name);
+        }
+        if let Some(interval) = config.compact_interval {
+            if compacted_at.elapsed() >= interval {
+                compacted_at = Instant::now();
+                compact(&config, &mut *broadcasted_dadjokes);
+            }
+        }
+        if let Some(refresh) = config.voice_refresh {
+            if voices_loaded_at.elapsed() >= refresh {
+                voices_loaded_at = Instant::now();
+                // The voices from before still work, so a failed refresh is only worth a warning
+                match load_voices(&config, &http, &client).await {
+                    Ok(refreshed) => {
+                        info!("Refreshed the voices, found {}", refreshed.len());
+                        voices = refreshed;
+                    }
+                    Err(e) => warn!(
+                        "Could not refresh the voices, keeping the old ones: {:#}",
+                        e
+                    ),
+                }
+            }
+        }
+        // Let a replay finish before the next joke is queued after it
+        if let Some(sink) = &sink {
+            audio::wait_for_playback(sink, || {
+                if let Some(shown) = &mut shown {
+                    shown.redraw_if_resized(&cursor, &terminal);
+                }
+            })
+            .await;
+        }
+        let voice = voices.pick(&mut rand);
+        let cycle = async {
+            let reddit_posts = reddit::load_posts(&config.reddit, &http);
+            let posts = if open_streak.ready_after_next_open() {
+                // The space was open at the last poll and most likely still is, so save some time
+                // by loading the posts while checking. A closed space or a failed check drops the
+                // posts that are still loading
+                let ready = space_is_ready(&config, &http, &mut open_streak);
+                tokio::pin!(ready, reddit_posts);
+                tokio::select! {
+                    ready = &mut ready => {
+                        if !ready? {
+                            return Ok(None);
+                        }
+                        reddit_posts.await
+                    }
+                    posts = &mut reddit_posts => {
+                        if !ready.await? {
+                            return Ok(None);
+                        }
+                        posts
+                    }
+                }
+            } else {
+                if !space_is_ready(&config, &http, &mut open_streak).await? {
+                    return Ok(None);
+                }
+                reddit_posts.await
+            };
+            if PAUSED.load(Ordering::SeqCst) {
+                info!("Paused, not telling a joke");
+                return Ok(None);
+            }
+            // Every joke is recorded before it is told, so the next one in the batch is a new joke
+            // and a crash halfway through doesn't tell the first ones again
+            let mut posts = posts;
+            let mut last_shown = None;
+            for index in 0..config.batch_size {
+                if index > 0 {
+                    shutdown::sleep(config.batch_pause).await;
+                }
+                let shown = run(
+                    &config,
+                    &http,
+                    posts.clone(),
+                    &mut *broadcasted_dadjokes,
+                    &mut recent_jokes,
+                    &client,
+                    sink.as_ref(),
+                    &cursor,
+                    &terminal,
+                    voice,
+                    &mut rand,
+                )
+                .await?;
+                let mut shown = match shown {
+                    Some(shown) => shown,
+                    None => break,
+                };
+                // Keep the joke on screen for a while, even if the next cycle starts soon
+                shutdown::sleep_with(config.display_hold, || {
+                    replay_if_requested(&config, &mut shown, sink.as_ref(), &cursor, &terminal);
+                    shown.redraw_if_resized(&cursor, &terminal)
+                })
+                .await;
+                // Also when NO_RECORD is set
+                posts.retain(|post| post.id != shown.post().id);
+                last_shown = Some(shown);
+                if posts.is_empty() || shutdown::is_requested() {
+                    break;
+                }
+            }
+            Ok::<_, anyhow::Error>(last_shown)
+        };
+        // Dropping the cycle cancels whatever request or playback is in progress
+        let result = tokio::select! {
+            result = cycle => result,
+            _ = shutdown::requested() => Ok(None),
+        };
+        let once_exit_code = match &result {
+            Ok(Some(_)) => 0,
+            Ok(None) => EXIT_NO_JOKE,
+            Err(_) => 1,
+        };
+        match result {
+            Ok(Some(joke)) => {
+                health::cycle_succeeded();
+                failures = 0;
+                shown = Some(joke);
+            }
+            Ok(None) => {
+                health::cycle_succeeded();
+                failures = 0;
+            }
+            Err(e) => {
+                error!("Could not generate pun: {:#}", e);
+                match error::recovery(&e) {
+                    error::Recovery::Backoff => failures += 1,
+                    error::Recovery::Retry => {}
+                    error::Recovery::Exit => {
+                        exit_code = 1;
+                        break;
+                    }
+                }
+            }
+        }
+        logging::set_joke_id(None);
+        if config.run_once {
+            exit_code = once_exit_code;
+            break;
+        }
+        let interval = backoff_interval(config.poll_interval, failures, config.max_backoff);
+        let interval = with_jitter(interval, config.poll_jitter, &mut rand);
+        if failures > 0 {
+            info!(
+                "{} cycles in a row failed, waiting {:?}",
+                failures, interval
+            );
+        }
+        shutdown::sleep_with(interval, || {
+            if let Some(shown) = &mut shown {
+                replay_if_requested(&config, shown, sink.as_ref(), &cursor, &terminal);
+                shown.redraw_if_resized(&cursor, &terminal);
+            }
+        })
+        .await;
+    }
+
+    info!("Shutting down");
+    restore_terminal(config.display.is_tty, &cursor, &terminal);
+    std::process::exit(exit_code);
+}
+
+/// Forget the used ids and history entries past `USED_ID_TTL_DAYS` and `MAX_USED_IDS`, so the
+/// files don't keep growing. Failures are only logged, the full files still work.
+fn compact(config: &Config, used_jokes: &mut dyn UsedJokes) {
+    // NO_RECORD leaves the used ids untouched
+    if !config.no_record {
+        match used_jokes.compact(config.used_ids.max) {
+            Ok(0) => debug!("No used ids to prune"),
+            Ok(pruned) => info!("Pruned {} used ids", pruned),
+            Err(e) => warn!("Could not compact the used ids: {:#}", e),
+        }
+    }
+    match history::compact(
+        config.history_file,
+        config.used_ids.ttl,
+        config.used_ids.max,
+    ) {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} history entries", pruned),
+        Err(e) => warn!("Could not compact the history: {:#}", e),
+    }
+}
+
+/// Toggle `PAUSED` whenever the process receives `SIGUSR1`, e.g. from `pkill -USR1 dadjokes`.
+#[cfg(unix)]
+fn listen_for_pause() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!(
+                "Could not listen for SIGUSR1, jokes can't be paused: {:?}",
+                e
+            );
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            if PAUSED.fetch_xor(true, Ordering::SeqCst) {
+                info!("Resumed telling jokes");
+            } else {
+                info!("Paused telling jokes, send SIGUSR1 again to resume");
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn listen_for_pause() {}
+
+/// Undo the changes made to the terminal at startup, if the jokes were drawn on it (`is_tty`).
+fn restore_terminal(
+    is_tty: bool,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+) {
+    keys::stop();
+    if !is_tty {
+        return;
+    }
     terminal
         .clear(crossterm::ClearType::All)
         .expect("Could not clear terminal");
+    cursor.goto(0, 0).expect("Could not move cursor");
+    cursor.show().expect("Could not show cursor");
+}
 
-    let mut broadcasted_dadjokes = load_used_ids().unwrap_or_default();
-    let client = PollyClient::new_with(
-        HttpClient::new().expect("Could not make http client"),
-        StaticProvider::new_minimal(
-            AWS_POLLY_ACCESS_KEY.to_owned(),
-            AWS_POLLY_SECRET_ACCESS_KEY.to_owned(),
-        ),
-        Region::EuWest1,
-    );
+/// The voices of Polly that jokes can be read with.
+async fn load_voices(
+    config: &Config,
+    http: &Http,
+    client: &PollyClient,
+) -> Result<tts::Voices, anyhow::Error> {
+    tts::Voices::new(
+        tts::load_voices(&config.tts, http, client).await?,
+        &config.tts,
+    )
+}
 
-    let voices = client
-        .describe_voices(rusoto_polly::DescribeVoicesInput {
-            language_code: Some(String::from("en-US")),
-            ..Default::default()
+/// Check the spacestate, returning whether the space has been open long enough to tell a joke.
+async fn space_is_ready(
+    config: &Config,
+    http: &Http,
+    open_streak: &mut spacestate::OpenStreak,
+) -> Result<bool, anyhow::Error> {
+    let space_is_open = http
+        .with_retries("get spacestate", || {
+            spacestate::space_is_open(&http.client, &config.spacestate)
         })
-        .sync()
-        .expect("Could not describe voices");
-    let voices = voices.voices.unwrap();
-    let mut rand = rand::thread_rng();
-    let device = rodio::default_output_device().expect("Could not find default audio device");
-    info!("Playing audio on {:?}", device.name());
-
-    loop {
-        let voice = voices.choose(&mut rand).unwrap();
-        if let Err(e) = run(
-            &mut broadcasted_dadjokes,
-            &client,
-            &device,
-            &cursor,
-            &terminal,
-            voice,
-        ) {
-            error!("Could not generate pun: {:?}", e);
-        }
-        std::thread::sleep(std::time::Duration::from_secs(30));
+        .await
+        .map_err(|e| AppError::Spacestate(e.into()))?;
+    metrics::set(&metrics::SPACE_OPEN, space_is_open as u64);
+    dashboard::set_space_open(space_is_open);
+    if !space_is_open {
+        info!("Space is not open");
     }
+    Ok(open_streak.update(space_is_open))
 }
-fn run(
-    used_jokes: &mut Vec<String>,
+
+#[allow(clippy::too_many_arguments)]
+async fn run(
+    config: &Config,
+    http: &Http,
+    posts: Vec<reddit::RedditPost>,
+    used_jokes: &mut dyn UsedJokes,
+    recent_jokes: &mut storage::RecentJokes,
     client: &PollyClient,
-    device: &rodio::Device,
+    sink: Option<&rodio::Sink>,
     cursor: &crossterm::TerminalCursor,
     terminal: &crossterm::Terminal,
     voice: &rusoto_polly::Voice,
-) -> Result<(), failure::Error> {
-    if !space_is_open().context("Could not get spacestate")? {
-        info!("Space is not open");
-        return Ok(());
+    rng: &mut StdRng,
+) -> Result<Option<display::ShownJoke>, anyhow::Error> {
+    if posts.is_empty() {
+        return Err(AppError::Reddit(anyhow!(
+            "Did not find a single post, see the errors above for why reddit returned none"
+        ))
+        .into());
+    }
+    if config.mode == Mode::Daily
+        && storage::told_today(used_jokes, &SystemClock).map_err(AppError::Io)?
+    {
+        debug!("Already told the joke of the day");
+        return Ok(None);
     }
-    let posts = load_newest_reddit_posts().context("Could not load reddit posts")?;
-    let highest = match posts.iter().max_by_key(|p| p.score) {
+    let mut posts = reddit::filter_unsuitable(posts, config.reddit.skip_nsfw);
+    if config.reddit.skip_removed {
+        posts = reddit::filter_removed(posts);
+    }
+    if config.reddit.require_selftext {
+        posts = reddit::filter_without_selftext(posts);
+    }
+    let posts = reddit::filter_announcements(posts, &config.reddit.announcement_filter);
+    let posts = blocklist::filter_blocked(posts, &config.blocklist);
+    let posts = reddit::filter_by_min_score(posts, config.reddit.min_score);
+    let posts = reddit::filter_by_reception(
+        posts,
+        config.reddit.min_comments,
+        config.reddit.min_upvote_ratio,
+    );
+    if posts.is_empty() {
+        info!(
+            "No posts above threshold {} with enough comments and upvotes",
+            config.reddit.min_score
+        );
+        return Ok(None);
+    }
+    let posts = storage::filter_untold(posts, used_jokes, config.used_ids.dedupe_by_title)
+        .map_err(AppError::Io)?;
+    if posts.is_empty() {
+        if !ALL_TOLD_LOGGED.swap(true, Ordering::SeqCst) {
+            info!("All current posts already told, waiting for new ones");
+        }
+        return Ok(None);
+    }
+    ALL_TOLD_LOGGED.store(false, Ordering::SeqCst);
+    let posts = recent_jokes.filter(posts);
+    let quiet = config.quiet_hours.is_quiet_now(&SystemClock);
+    if quiet && !config.quiet_hours.display {
+        // Don't record the joke, so it can still be told once quiet hours are over
+        info!("Quiet hours, not telling a joke");
+        return Ok(None);
+    }
+    let selected = match config.mode {
+        Mode::Continuous => reddit::select_post(&config.reddit, &posts, rng, &SystemClock),
+        Mode::Daily => posts.iter().max_by_key(|p| p.score),
+    };
+    let highest = match selected {
         Some(post) => post,
         None => bail!("Did not find a single post"),
     };
-    if used_jokes.contains(&highest.id) {
-        info!("Ignoring joke that has already been told: {:?}", highest);
-        return Ok(());
-    }
+    logging::set_joke_id(Some(&highest.id));
     info!("{:#?}", highest);
-    used_jokes.push(highest.id.clone());
-    let mut output =
-        File::create(&*USED_IDS_FILE).context("Could not open USED_IDS_FILE for writing")?;
-    for id in used_jokes {
-        writeln!(&mut output, "{}", id).context("Could not save USER_IDS_FILE")?;
-    }
-
-    let result = client
-        .synthesize_speech(rusoto_polly::SynthesizeSpeechInput {
-            output_format: String::from("mp3"),
-            text: format!("{}\n\n{}", highest.title, highest.selftext),
-            voice_id: voice.id.clone().unwrap(),
-            ..Default::default()
-        })
-        .sync()
-        .context("Could not synthesize speech")?;
-    let stream = result.audio_stream.unwrap();
-    let decoder = rodio::Decoder::new(Cursor::new(stream)).context("Could not create decoder")?;
-    rodio::play_raw(device, decoder.convert_samples());
-
-    let (width, height) = terminal.terminal_size();
-    terminal
-        .clear(crossterm::ClearType::All)
-        .context("Could not clear screen")?;
-
-    {
-        // TODO What if this is wider than the terminal?
-        let x = (width - highest.title.len() as u16) / 2;
-        let y = height / 2 - 1;
-        cursor.goto(x, y).context("Could not move cursor")?;
-        terminal
-            .write(&highest.title)
-            .context("Could not write title")?;
+    if !config.no_record {
+        used_jokes.record(highest).map_err(AppError::Io)?;
     }
-    {
-        // TODO What if this is wider than the terminal?
-        let mut y = height / 2 + 1;
-        for line in highest.selftext.split('\n') {
-            let x = (width - line.len() as u16) / 2;
-            cursor.goto(x, y).context("Could not move cursor")?;
-            terminal.write(line).context("Could not write selftext")?;
-            y += 1;
+    recent_jokes.record(&highest.id);
+    let mut joke = highest.clone();
+    if config.reddit.use_top_comment && joke.selftext.trim().is_empty() {
+        match reddit::load_top_comment(&config.reddit, http, &joke).await {
+            Ok(Some(comment)) => {
+                info!("Using the top comment as the punchline");
+                joke.selftext = comment;
+            }
+            Ok(None) => info!("No comment to use as the punchline, only telling the title"),
+            Err(e) => warn!("Could not load comments, only telling the title: {:#}", e),
         }
     }
+    let joke = match config.tts.long_text_mode {
+        tts::LongTextMode::Truncate => tts::truncate_joke(&joke, config.tts.max_text_chars),
+        tts::LongTextMode::Chunk => joke,
+    };
+    let highest = &joke;
+    dashboard::joke_started(highest);
 
-    Ok(())
-}
-
-fn space_is_open() -> Result<bool, reqwest::Error> {
-    let mut response = reqwest::get("https://spacestate.pixelbar.nl/spacestate.php")?;
-    let response: Value = response.json()?;
-    Ok(if let Some(Value::String(s)) = response.get("state") {
-        s == "open"
+    let sink = if config.dry_run {
+        info!("Dry run, not synthesizing speech");
+        None
+    } else if quiet {
+        info!("Quiet hours, only displaying the joke");
+        None
     } else {
-        false
-    })
+        sink
+    };
+    let (mut shown, polly_voice) = match sink {
+        Some(sink) => {
+            read_out(config, http, highest, client, sink, cursor, terminal, voice).await?
+        }
+        None => {
+            let shown = display::show_joke(
+                &config.display,
+                cursor,
+                terminal,
+                highest,
+                config.punchline_delay,
+            )
+            .await?;
+            (shown, None)
+        }
+    };
+    // Also for jokes that are only displayed, like they are on the webhook
+    metrics::increment(&metrics::JOKES_TOLD);
+    history::record(config.history_file, highest, polly_voice);
+    webhook::announce(&config.webhook, &http.client, highest);
+    hook::joke_told(&config.hook, highest, polly_voice);
+
+    if let Some(sink) = sink {
+        audio::wait_for_playback(sink, || shown.redraw_if_resized(cursor, terminal)).await;
+    }
+    dashboard::joke_finished();
+
+    Ok(Some(shown))
 }
 
-fn load_newest_reddit_posts() -> Result<Vec<RedditPost>, reqwest::Error> {
-    let mut response = reqwest::get(&*REDDIT_URL)?;
-    let json: Value = response.json()?;
-    let mut result = Vec::new();
-
-    if let Some(Value::Array(a)) = json.pointer("/data/children") {
-        for child in a {
-            let id = child.pointer("/data/id");
-            let title = child.pointer("/data/title");
-            let selftext = child.pointer("/data/selftext");
-            let score = child.pointer("/data/score");
-
-            if let (
-                Some(Value::String(id)),
-                Some(Value::String(title)),
-                Some(Value::String(selftext)),
-                Some(Value::Number(score)),
-            ) = (id, title, selftext, score)
-            {
-                result.push(RedditPost {
-                    id: id.to_owned(),
-                    title: title.to_owned(),
-                    selftext: selftext.to_owned(),
-                    score: score.as_i64().unwrap_or_default(),
-                });
-            } else {
-                error!("Missing values of {:?}", child);
-                error!("id: {:?}", id);
-                error!("title: {:?}", title);
-                error!("selftext: {:?}", selftext);
-                error!("score: {:?}", score);
+/// Synthesize `post` and queue it on `sink`, while showing it. Returns once everything is queued,
+/// with the Polly voice it was read by, `None` if (a part of) it was read by the fallback.
+#[allow(clippy::too_many_arguments)]
+async fn read_out<'a>(
+    config: &Config,
+    http: &Http,
+    post: &reddit::RedditPost,
+    client: &PollyClient,
+    sink: &rodio::Sink,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+    voice: &'a rusoto_polly::Voice,
+) -> Result<(display::ShownJoke, Option<&'a str>), anyhow::Error> {
+    let texts = tts::SpeechText::chunked(
+        &tts::spoken_text(&post.title, config.tts.spoken_urls),
+        &tts::spoken_text(&post.selftext, config.tts.spoken_urls),
+        config.tts.use_ssml,
+        config.punchline_delay,
+        &config.abbreviations,
+    );
+    let texts = match config.tts.intro_text {
+        Some(intro) => tts::SpeechText::with_intro(
+            texts,
+            &tts::spoken_text(intro, config.tts.spoken_urls),
+            config.tts.use_ssml,
+            &config.abbreviations,
+        ),
+        None => texts,
+    };
+    let texts = texts
+        .into_iter()
+        .map(|text| text.with_prosody(&config.tts.prosody))
+        .collect::<Vec<_>>();
+    let engine = tts::engine_for_voice(voice, config.tts.engine);
+    let voice_id = voice.id.as_ref().unwrap();
+    let (first, rest) = texts.split_first().unwrap();
+    let first = {
+        let _spinner = display::Spinner::start(&config.display, "Now synthesizing...");
+        tts::load_speech(&config.tts, http, client, voice_id, engine, first)
+            .await
+            .map_err(AppError::Polly)?
+    };
+    audio::append(sink, Arc::clone(&first.audio), first.encoding).map_err(AppError::Audio)?;
+
+    // This doesn't stream the audio of a part: rusoto only returns the body of Polly once it is
+    // complete, and the mp3 and ogg decoders need to seek. Instead the rest of a long joke is
+    // synthesized while the first part is already playing
+    let (shown, speech) = tokio::join!(
+        display::show_joke(
+            &config.display,
+            cursor,
+            terminal,
+            post,
+            config.punchline_delay
+        ),
+        async {
+            let mut speech = vec![first];
+            for text in rest {
+                let part = tts::load_speech(&config.tts, http, client, voice_id, engine, text)
+                    .await
+                    .map_err(AppError::Polly)?;
+                audio::append_silence(sink, text.pause_before());
+                audio::append(sink, Arc::clone(&part.audio), part.encoding)
+                    .map_err(AppError::Audio)?;
+                speech.push(part);
             }
+            Ok::<_, anyhow::Error>(speech)
         }
+    );
+    let (shown, speech) = (shown?, speech?);
+    audio::append_rimshot(sink, config.audio.rimshot_file);
+    let mut recording = audio::Recording::default();
+    for (text, part) in texts.iter().zip(&speech) {
+        recording.push(text.pause_before(), Arc::clone(&part.audio), part.encoding);
     }
+    // Only Polly voices are worth keeping, the fallback is just there to not miss a joke
+    let polly_voice = if speech.iter().any(|part| part.fallback) {
+        None
+    } else {
+        archive::save(
+            config.archive_dir,
+            config.tts.output_format,
+            post,
+            voice_id,
+            &recording,
+        );
+        Some(voice_id.as_str())
+    };
+    let shown = shown.with_recording(recording);
+    Ok((shown, polly_voice))
+}
 
-    Ok(result)
+/// Show `shown` again and play its audio on `sink` if `r` was pressed, without recording or
+/// announcing it. During quiet hours the joke is only shown.
+fn replay_if_requested(
+    config: &Config,
+    shown: &mut display::ShownJoke,
+    sink: Option<&rodio::Sink>,
+    cursor: &crossterm::TerminalCursor,
+    terminal: &crossterm::Terminal,
+) {
+    if !keys::replay_requested() {
+        return;
+    }
+    info!("Replaying joke {:?}", shown.post().id);
+    shown.redraw(cursor, terminal);
+    if config.quiet_hours.is_quiet_now(&SystemClock) {
+        return;
+    }
+    if let (Some(sink), Some(recording)) = (sink, shown.recording()) {
+        if let Err(e) = recording.replay(sink, config.audio.rimshot_file) {
+            warn!("Could not replay joke: {:#}", e);
+        }
+    }
 }
 
-#[derive(Debug)]
-struct RedditPost {
-    id: String,
-    title: String,
-    selftext: String,
-    score: i64,
+/// `base` doubled for every one of the consecutive `failures`, but at most `max`.
+fn backoff_interval(base: Duration, failures: u32, max: Duration) -> Duration {
+    let max = max.max(base);
+    // Anything above 2^16 is way past any sensible `max` anyway
+    let factor = 1 << failures.min(16);
+    base.checked_mul(factor)
+        .map_or(max, |interval| interval.min(max))
 }
 
-fn load_used_ids() -> std::io::Result<Vec<String>> {
-    let file = File::open(&*USED_IDS_FILE)?;
-    let lines = BufReader::new(file)
-        .lines()
-        .filter_map(|l| l.ok())
-        .filter(|l| !l.is_empty())
-        .collect();
-    Ok(lines)
+/// `interval` plus or minus a random duration of at most `jitter`, but at least a second.
+fn with_jitter(interval: Duration, jitter: Duration, rng: &mut impl Rng) -> Duration {
+    if jitter == Duration::from_secs(0) {
+        return interval;
+    }
+    let jitter = jitter.as_millis() as i64;
+    let millis = interval.as_millis() as i64 + rng.gen_range(-jitter, jitter + 1);
+    Duration::from_millis(millis.max(1000) as u64)
 }
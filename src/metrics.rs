@@ -0,0 +1,95 @@
+use crate::error::AppError;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub static JOKES_TOLD: AtomicU64 = AtomicU64::new(0);
+pub static POLLY_REQUESTS: AtomicU64 = AtomicU64::new(0);
+pub static POLLY_ERRORS: AtomicU64 = AtomicU64::new(0);
+pub static REDDIT_FETCH_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// 1 if the space was open at the last check, 0 otherwise
+pub static SPACE_OPEN: AtomicU64 = AtomicU64::new(0);
+
+pub fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn set(gauge: &AtomicU64, value: u64) {
+    gauge.store(value, Ordering::Relaxed);
+}
+
+/// The current metrics in the Prometheus text format.
+#[cfg(feature = "metrics")]
+fn render() -> String {
+    let metrics: &[(&str, &str, &str, &AtomicU64)] = &[
+        (
+            "jokes_told_total",
+            "counter",
+            "Number of jokes that have been told",
+            &JOKES_TOLD,
+        ),
+        (
+            "polly_requests_total",
+            "counter",
+            "Number of speech synthesis requests sent to Polly",
+            &POLLY_REQUESTS,
+        ),
+        (
+            "polly_errors_total",
+            "counter",
+            "Number of failed speech synthesis requests",
+            &POLLY_ERRORS,
+        ),
+        (
+            "reddit_fetch_errors_total",
+            "counter",
+            "Number of reddit listings that could not be loaded",
+            &REDDIT_FETCH_ERRORS,
+        ),
+        (
+            "space_open",
+            "gauge",
+            "Whether the space was open at the last check",
+            &SPACE_OPEN,
+        ),
+    ];
+    let mut result = String::new();
+    for (name, kind, help, value) in metrics {
+        result += &format!(
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n",
+            name = name,
+            help = help,
+            kind = kind,
+            value = value.load(Ordering::Relaxed)
+        );
+    }
+    result
+}
+
+/// Serve the metrics on `http://<addr>/metrics` from a background thread.
+#[cfg(feature = "metrics")]
+pub fn serve(addr: &str) -> Result<(), AppError> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| AppError::Config(format!("Could not listen on {:?}: {}", addr, e)))?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let header = "Content-Type: text/plain; version=0.0.4"
+                    .parse::<tiny_http::Header>()
+                    .unwrap();
+                tiny_http::Response::from_string(render()).with_header(header)
+            } else {
+                tiny_http::Response::from_string("Not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                log::warn!("Could not respond to metrics request: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn serve(_addr: &str) -> Result<(), AppError> {
+    Err(AppError::Config(String::from(
+        "METRICS_ADDR is set, but dadjokes was built without the metrics feature",
+    )))
+}
@@ -0,0 +1,110 @@
+use rusoto_core::request::{DispatchSignedRequestFuture, HttpResponse};
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, DispatchSignedRequest, HttpDispatchError};
+use std::time::Duration;
+
+/// `proxy_url` for the shared http client, `None` to use the proxy in the standard environment
+/// variables, if any.
+pub fn configured_proxy(proxy_url: Option<&str>) -> Option<reqwest::Proxy> {
+    proxy_url.map(|url| reqwest::Proxy::all(url).expect("Invalid PROXY_URL"))
+}
+
+/// The proxy the requests go through with `PROXY_URL` set to `proxy_url`, without its password so
+/// it can be logged.
+///
+/// Polly and reddit are reached over https, so that's the proxy of the environment that matters.
+pub fn proxy_in_use(proxy_url: Option<&str>) -> Option<String> {
+    let url = match proxy_url {
+        Some(url) => url.to_owned(),
+        None => ["HTTPS_PROXY", "https_proxy"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|url| !url.is_empty())?,
+    };
+    Some(match reqwest::Url::parse(&url) {
+        Ok(mut url) => {
+            if url.password().is_some() {
+                let _ = url.set_password(Some("***"));
+            }
+            url.to_string()
+        }
+        Err(_) => url,
+    })
+}
+
+/// Sends the requests of rusoto with a reqwest client, so they go through the same proxy as the
+/// other requests. The http client of rusoto itself can't use a proxy.
+pub struct ReqwestDispatcher {
+    pub client: reqwest::Client,
+}
+
+impl DispatchSignedRequest for ReqwestDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        Box::pin(dispatch(self.client.clone(), request, timeout))
+    }
+}
+
+async fn dispatch(
+    client: reqwest::Client,
+    request: SignedRequest,
+    timeout: Option<Duration>,
+) -> Result<HttpResponse, HttpDispatchError> {
+    let error = |message: String| HttpDispatchError::new(message);
+    let method = reqwest::Method::from_bytes(request.method().as_bytes())
+        .map_err(|e| error(format!("Invalid method {:?}: {}", request.method(), e)))?;
+    let mut url = format!(
+        "{}://{}{}",
+        request.scheme(),
+        request.hostname(),
+        request.canonical_path()
+    );
+    if !request.canonical_query_string().is_empty() {
+        url.push('?');
+        url.push_str(request.canonical_query_string());
+    }
+
+    let mut builder = client.request(method, &url);
+    for (name, values) in request.headers() {
+        for value in values {
+            builder = builder.header(name.as_str(), value.as_slice());
+        }
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder = match request.payload {
+        Some(SignedRequestPayload::Buffer(bytes)) => builder.body(bytes.to_vec()),
+        // Polly only sends small json bodies
+        Some(SignedRequestPayload::Stream(_)) => {
+            return Err(error(String::from(
+                "Streaming request bodies are not supported through a proxy",
+            )))
+        }
+        None => builder,
+    };
+
+    let response = builder
+        .send()
+        .await
+        .map_err(|e| error(format!("Error during dispatch: {}", e)))?;
+    let status = response.status();
+    let mut headers = reqwest::header::HeaderMap::<String>::with_capacity(response.headers().len());
+    for (name, value) in response.headers() {
+        if let Ok(value) = value.to_str() {
+            headers.append(name, value.to_owned());
+        }
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| error(format!("Error reading response: {}", e)))?;
+    Ok(HttpResponse {
+        status,
+        body: ByteStream::from(body.to_vec()),
+        headers,
+    })
+}
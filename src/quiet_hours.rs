@@ -0,0 +1,129 @@
+use crate::clock::Clock;
+use chrono::NaiveTime;
+use log::warn;
+
+/// The clock `QUIET_START` and `QUIET_END` are in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuietTimezone {
+    /// The timezone of the system, including daylight saving time
+    Local,
+    Utc,
+}
+
+/// Parse a 24 hour time like `23:30`
+pub fn parse_time(time: &str) -> chrono::ParseResult<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+}
+
+/// When no jokes are read out
+pub struct QuietHours {
+    /// From `QUIET_START` up to `QUIET_END`, `None` if there are no quiet hours
+    hours: Option<(NaiveTime, NaiveTime)>,
+    timezone: QuietTimezone,
+    /// Still show jokes during quiet hours, only without sound
+    pub display: bool,
+}
+
+impl QuietHours {
+    /// Quiet hours from `start` up to `end`, which are ignored unless both are set.
+    pub fn new(
+        start: Option<NaiveTime>,
+        end: Option<NaiveTime>,
+        timezone: QuietTimezone,
+        display: bool,
+    ) -> QuietHours {
+        let hours = match (start, end) {
+            (Some(start), Some(end)) => Some((start, end)),
+            (None, None) => None,
+            _ => {
+                warn!("Only one of QUIET_START and QUIET_END is set, ignoring quiet hours");
+                None
+            }
+        };
+        QuietHours {
+            hours,
+            timezone,
+            display,
+        }
+    }
+
+    /// Whether it is currently between `QUIET_START` and `QUIET_END`.
+    pub fn is_quiet_now(&self, clock: &dyn Clock) -> bool {
+        match self.hours {
+            Some(hours) => is_quiet(clock, hours, self.timezone),
+            None => false,
+        }
+    }
+}
+
+/// Whether the time of `clock` in `timezone` is inside the quiet `hours`.
+fn is_quiet(
+    clock: &dyn Clock,
+    (start, end): (NaiveTime, NaiveTime),
+    timezone: QuietTimezone,
+) -> bool {
+    let now = match timezone {
+        QuietTimezone::Local => clock.now().with_timezone(&chrono::Local).time(),
+        QuietTimezone::Utc => clock.now().time(),
+    };
+    is_between(now, start, end)
+}
+
+/// Whether `time` is in the window from `start` up to `end`, which wraps around midnight if `end`
+/// is earlier than `start`.
+fn is_between(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use chrono::TimeZone;
+
+    fn time(time: &str) -> NaiveTime {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn is_between_same_day() {
+        let (start, end) = (time("09:00"), time("17:00"));
+        assert!(is_between(time("09:00"), start, end));
+        assert!(is_between(time("12:30"), start, end));
+        assert!(!is_between(time("17:00"), start, end));
+        assert!(!is_between(time("03:00"), start, end));
+    }
+
+    #[test]
+    fn is_between_crosses_midnight() {
+        let (start, end) = (time("23:00"), time("07:00"));
+        assert!(is_between(time("23:30"), start, end));
+        assert!(is_between(time("00:00"), start, end));
+        assert!(is_between(time("06:59"), start, end));
+        assert!(!is_between(time("07:00"), start, end));
+        assert!(!is_between(time("12:00"), start, end));
+    }
+
+    #[test]
+    fn is_between_empty_window() {
+        assert!(!is_between(time("12:00"), time("12:00"), time("12:00")));
+    }
+
+    #[test]
+    fn is_quiet_uses_the_clock() {
+        let at = |hour, minute| FixedClock(chrono::Utc.ymd(2019, 6, 1).and_hms(hour, minute, 0));
+        let hours = (time("23:00"), time("07:00"));
+        assert!(is_quiet(&at(23, 30), hours, QuietTimezone::Utc));
+        assert!(is_quiet(&at(6, 59), hours, QuietTimezone::Utc));
+        assert!(!is_quiet(&at(7, 0), hours, QuietTimezone::Utc));
+        assert!(!is_quiet(&at(12, 0), hours, QuietTimezone::Utc));
+
+        let local_midnight = chrono::Local.ymd(2019, 6, 1).and_hms(0, 30, 0);
+        let clock = FixedClock(local_midnight.with_timezone(&chrono::Utc));
+        assert!(is_quiet(&clock, hours, QuietTimezone::Local));
+    }
+}
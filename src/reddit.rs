@@ -0,0 +1,903 @@
+use crate::clock::Clock;
+use crate::http::Http;
+use crate::metrics;
+use anyhow::{bail, Context};
+use log::{debug, error, info};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Where the jokes come from, and which of them are told
+pub struct RedditConfig {
+    pub source: RedditSource,
+    /// Sent with every request, see `user_agent`
+    pub user_agent: String,
+    pub selection_mode: SelectionMode,
+    /// The lowest score a post counts with in the weighted `selection_mode`
+    pub selection_score_floor: f64,
+    /// How fast posts drop in the trending `selection_mode`
+    pub trending_gravity: f64,
+    pub min_score: i64,
+    pub min_comments: Option<u64>,
+    pub min_upvote_ratio: Option<f64>,
+    pub skip_nsfw: bool,
+    pub skip_removed: bool,
+    pub require_selftext: bool,
+    /// Read the top comment as the punchline of posts without a selftext
+    pub use_top_comment: bool,
+    pub announcement_filter: AnnouncementFilter,
+}
+
+/// The user agent for `REDDIT_USERNAME`. Reddit rate limits generic user agents a lot harder, so
+/// tell them who we are.
+pub fn user_agent(username: Option<&str>) -> String {
+    match username {
+        Some(username) => format!("dadjokes-bot/{} by {}", env!("CARGO_PKG_VERSION"), username),
+        None => format!("dadjokes-bot/{}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    /// Always pick the post with the highest score
+    Top,
+    /// Pick a random post, with the probability proportional to its score
+    Weighted,
+    /// Pick the post with the highest score for its age, see `trending_score`
+    Trending,
+}
+
+/// How the posts of a subreddit are sorted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Listing {
+    New,
+    Hot,
+    Top,
+}
+
+impl Listing {
+    fn as_str(self) -> &'static str {
+        match self {
+            Listing::New => "new",
+            Listing::Hot => "hot",
+            Listing::Top => "top",
+        }
+    }
+}
+
+/// The period the top listing is taken over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeRange {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl TimeRange {
+    fn as_str(self) -> &'static str {
+        match self {
+            TimeRange::Hour => "hour",
+            TimeRange::Day => "day",
+            TimeRange::Week => "week",
+            TimeRange::Month => "month",
+            TimeRange::Year => "year",
+            TimeRange::All => "all",
+        }
+    }
+}
+
+/// The json url of a listing of `subreddit`, e.g. `https://www.reddit.com/r/dadjokes/new.json`.
+///
+/// `subreddit` can also be a multireddit like `/user/<name>/m/<multi>`, which has the same
+/// listings. `time` is only sent for the top listing, reddit ignores it for the others.
+pub fn build_reddit_url(subreddit: &str, listing: Listing, time: TimeRange, limit: u32) -> String {
+    let subreddit = subreddit.trim_matches('/');
+    let path = match subreddit.find('/').map(|split| subreddit.split_at(split)) {
+        Some(("user", multireddit)) | Some(("u", multireddit)) => format!("user{}", multireddit),
+        _ => format!("r/{}", subreddit.trim_start_matches("r/")),
+    };
+    match listing {
+        Listing::Top => format!(
+            "https://www.reddit.com/{}/{}.json?t={}&limit={}",
+            path,
+            listing.as_str(),
+            time.as_str(),
+            limit
+        ),
+        _ => format!(
+            "https://www.reddit.com/{}/{}.json?limit={}",
+            path,
+            listing.as_str(),
+            limit
+        ),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedditPost {
+    pub id: String,
+    pub title: String,
+    pub selftext: String,
+    pub score: i64,
+    pub over_18: bool,
+    pub spoiler: bool,
+    pub stickied: bool,
+    /// Not set for link and image posts, whose punchline can't be read out
+    pub is_self: bool,
+    /// The subreddit the post was found in, without the `r/` prefix
+    pub subreddit: String,
+    /// The path of the post on reddit, like `/r/dadjokes/comments/<id>/<title>/`
+    pub permalink: String,
+    pub author: String,
+    pub flair: Option<String>,
+    pub num_comments: u64,
+    /// The share of the votes that are upvotes, 1.0 when reddit didn't say
+    pub upvote_ratio: f64,
+    pub locked: bool,
+    /// Unix timestamp of when the post was made, `None` when reddit didn't say, like for jokes
+    /// from a file
+    pub created_utc: Option<i64>,
+    /// Why the post was removed, like `moderator` or `deleted`, `None` if it wasn't
+    pub removed_by_category: Option<String>,
+}
+
+/// An empty self post, with the defaults that are used for fields reddit leaves out.
+impl Default for RedditPost {
+    fn default() -> RedditPost {
+        RedditPost {
+            id: String::new(),
+            title: String::new(),
+            selftext: String::new(),
+            score: 1,
+            over_18: false,
+            spoiler: false,
+            stickied: false,
+            is_self: true,
+            subreddit: String::new(),
+            permalink: String::new(),
+            author: String::new(),
+            flair: None,
+            num_comments: 0,
+            upvote_ratio: 1.0,
+            locked: false,
+            created_utc: None,
+            removed_by_category: None,
+        }
+    }
+}
+
+/// Where the jokes are loaded from
+pub enum RedditSource {
+    /// The listing urls of `REDDIT_URL` or `REDDIT_SUBREDDIT`
+    Urls(Vec<String>),
+    /// A local file of jokes, to demo the bot without internet
+    File(&'static str),
+}
+
+pub async fn load_posts(config: &RedditConfig, http: &Http) -> Vec<RedditPost> {
+    match &config.source {
+        RedditSource::Urls(urls) => load_newest_reddit_posts(http, &config.user_agent, urls).await,
+        RedditSource::File(path) => match load_file_posts(path) {
+            Ok(posts) => posts,
+            Err(e) => {
+                error!("Could not load jokes from {:?}: {:#}", path, e);
+                Vec::new()
+            }
+        },
+    }
+}
+
+/// Read the jokes in `path`. The file is read again every cycle, so it can be edited while the
+/// bot is running.
+fn load_file_posts(path: &str) -> Result<Vec<RedditPost>, anyhow::Error> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {:?}", path))?;
+    parse_joke_file(&content)
+}
+
+/// Parse a file of jokes, which is either a saved reddit listing, a json array of jokes or one
+/// json joke per line.
+///
+/// A joke is an object with at least a `title`, and the same fields reddit uses otherwise. The
+/// `id` and `score` are made up when they are missing.
+fn parse_joke_file(content: &str) -> Result<Vec<RedditPost>, anyhow::Error> {
+    let jokes = match serde_json::from_str(content) {
+        Ok(json @ Value::Object(_)) if json.pointer("/data/children").is_some() => {
+            return Ok(parse_reddit_posts(&json));
+        }
+        Ok(Value::Array(jokes)) => jokes,
+        _ => content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Line {} is not a json joke", index + 1))
+            })
+            .collect::<Result<Vec<Value>, _>>()?,
+    };
+    Ok(jokes.into_iter().filter_map(parse_file_joke).collect())
+}
+
+/// A joke from a file as if it came from reddit, so the rest of the pipeline doesn't know the
+/// difference.
+fn parse_file_joke(mut joke: Value) -> Option<RedditPost> {
+    let data = joke.as_object_mut()?;
+    let title = data.get("title")?.as_str()?.to_owned();
+    let selftext = data.entry("selftext").or_insert_with(|| json!(""));
+    let selftext = selftext.as_str()?.to_owned();
+    // Based on the text, so the used ids keep working when jokes are added to the file
+    data.entry("id").or_insert_with(|| {
+        let hash = Sha256::digest(format!("{}\n{}", title, selftext).as_bytes());
+        json!(format!("file-{:x}", hash)[..17])
+    });
+    data.entry("score").or_insert_with(|| json!(1));
+    parse_reddit_post(&json!({ "data": joke }))
+}
+
+pub async fn load_newest_reddit_posts(
+    http: &Http,
+    user_agent: &str,
+    urls: &[String],
+) -> Vec<RedditPost> {
+    let mut seen_ids = HashSet::new();
+    let mut result = Vec::new();
+
+    for url in urls {
+        let load = || load_reddit_posts(&http.client, user_agent, url);
+        match http.with_retries("load reddit posts", load).await {
+            Ok(posts) if posts.is_empty() => info!("reddit returned no posts from {:?}", url),
+            Ok(posts) => {
+                for post in posts {
+                    if seen_ids.insert(post.id.clone()) {
+                        result.push(post);
+                    }
+                }
+            }
+            Err(e) => {
+                metrics::increment(&metrics::REDDIT_FETCH_ERRORS);
+                error!("Could not load reddit posts from {:?}: {:#}", url, e)
+            }
+        }
+    }
+
+    result
+}
+
+async fn load_reddit_posts(
+    client: &reqwest::Client,
+    user_agent: &str,
+    url: &str,
+) -> Result<Vec<RedditPost>, anyhow::Error> {
+    let json = load_reddit_json(client, user_agent, url).await?;
+    if json.pointer("/data/children").is_none() {
+        bail!("reddit returned json without a listing of posts: {}", json);
+    }
+    Ok(parse_reddit_posts(&json))
+}
+
+async fn load_reddit_json(
+    client: &reqwest::Client,
+    user_agent: &str,
+    url: &str,
+) -> Result<Value, anyhow::Error> {
+    let response = client
+        .get(url)
+        .header(USER_AGENT, user_agent)
+        .send()
+        .await?;
+    // Reddit serves an html page instead of json when it's rate limiting us
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with("application/json") {
+        bail!(
+            "reddit returned non-JSON (likely rate limited): status {}, content type {:?}",
+            response.status(),
+            content_type
+        );
+    }
+    let status = response.status();
+    let json = response.json().await?;
+    // When reddit is down or rate limiting us it can also respond with an error object
+    if let Some(message) = reddit_error(&json) {
+        bail!("reddit returned an error: {} (status {})", message, status);
+    }
+    if !status.is_success() {
+        bail!("reddit returned status {}", status);
+    }
+    Ok(json)
+}
+
+/// The message of an error response like `{"message": "Too Many Requests", "error": 429}`.
+fn reddit_error(json: &Value) -> Option<String> {
+    let error = json.get("error")?;
+    let message = json
+        .get("message")
+        .or_else(|| json.get("reason"))
+        .and_then(Value::as_str)
+        .unwrap_or("no message");
+    Some(format!("{} ({})", message, error))
+}
+
+/// The body of the highest scoring top level comment on `post`, `None` if it has no comments
+/// worth reading out.
+pub async fn load_top_comment(
+    config: &RedditConfig,
+    http: &Http,
+    post: &RedditPost,
+) -> Result<Option<String>, anyhow::Error> {
+    let url = format!(
+        "https://www.reddit.com{}.json",
+        post.permalink.trim_end_matches('/')
+    );
+    let json = http
+        .with_retries("load comments", || {
+            load_reddit_json(&http.client, &config.user_agent, &url)
+        })
+        .await?;
+    Ok(parse_top_comment(&json))
+}
+
+/// The comments json of a post is an array of the listing with the post itself, followed by the
+/// listing of its comments.
+fn parse_top_comment(json: &Value) -> Option<String> {
+    let comments = json.pointer("/1/data/children")?.as_array()?;
+    comments
+        .iter()
+        // "more" placeholders for collapsed comments have a different kind
+        .filter(|child| child.get("kind").and_then(Value::as_str) == Some("t1"))
+        .filter_map(|child| child.get("data"))
+        // Skip moderators and bots pinning the rules
+        .filter(|data| data.get("stickied").and_then(Value::as_bool) != Some(true))
+        .filter(|data| data.get("distinguished").is_none_or(Value::is_null))
+        .filter_map(|data| {
+            let body = data.get("body")?.as_str()?.trim();
+            let score = data.get("score")?.as_i64()?;
+            match body {
+                "" | "[deleted]" | "[removed]" => None,
+                body => Some((body, score)),
+            }
+        })
+        .max_by_key(|&(_, score)| score)
+        .map(|(body, _)| body.to_owned())
+}
+
+fn parse_reddit_posts(json: &Value) -> Vec<RedditPost> {
+    match json.pointer("/data/children") {
+        Some(Value::Array(children)) => children.iter().filter_map(parse_reddit_post).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A single child of a listing, `None` when it is missing any of the required values.
+fn parse_reddit_post(child: &Value) -> Option<RedditPost> {
+    let id = child.pointer("/data/id");
+    let title = child.pointer("/data/title");
+    let selftext = child.pointer("/data/selftext");
+    let score = child.pointer("/data/score");
+
+    if let (
+        Some(Value::String(id)),
+        Some(Value::String(title)),
+        Some(Value::String(selftext)),
+        Some(Value::Number(score)),
+    ) = (id, title, selftext, score)
+    {
+        let flag = |name: &str| {
+            child
+                .pointer(&format!("/data/{}", name))
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        };
+        Some(RedditPost {
+            id: id.to_owned(),
+            title: title.to_owned(),
+            selftext: selftext.to_owned(),
+            score: score.as_i64().unwrap_or_default(),
+            over_18: flag("over_18"),
+            spoiler: flag("spoiler"),
+            stickied: flag("stickied"),
+            is_self: child
+                .pointer("/data/is_self")
+                .and_then(Value::as_bool)
+                .unwrap_or(true),
+            subreddit: child
+                .pointer("/data/subreddit")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            permalink: child
+                .pointer("/data/permalink")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            author: child
+                .pointer("/data/author")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            flair: child
+                .pointer("/data/link_flair_text")
+                .and_then(Value::as_str)
+                .map(String::from),
+            num_comments: child
+                .pointer("/data/num_comments")
+                .and_then(Value::as_u64)
+                .unwrap_or_default(),
+            upvote_ratio: child
+                .pointer("/data/upvote_ratio")
+                .and_then(Value::as_f64)
+                .unwrap_or(1.0),
+            locked: flag("locked"),
+            created_utc: child
+                .pointer("/data/created_utc")
+                .and_then(Value::as_f64)
+                .map(|created| created as i64),
+            removed_by_category: child
+                .pointer("/data/removed_by_category")
+                .and_then(Value::as_str)
+                .map(String::from),
+        })
+    } else {
+        error!("Missing values of {:?}", child);
+        error!("id: {:?}", id);
+        error!("title: {:?}", title);
+        error!("selftext: {:?}", selftext);
+        error!("score: {:?}", score);
+        None
+    }
+}
+
+pub fn filter_unsuitable(posts: Vec<RedditPost>, skip_nsfw: bool) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            if skip_nsfw && p.over_18 {
+                debug!("Skipping NSFW post {:?}", p);
+                false
+            } else if p.spoiler {
+                debug!("Skipping spoiler post {:?}", p);
+                false
+            } else if p.stickied {
+                debug!("Skipping stickied post {:?}", p);
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+/// Remove the posts that were removed or locked since they were listed, so moderated content is
+/// never read out.
+pub fn filter_removed(posts: Vec<RedditPost>) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            let reason = match (&p.removed_by_category, p.selftext.as_str()) {
+                (Some(category), _) => format!("removed by {}", category),
+                (None, "[removed]") | (None, "[deleted]") => p.selftext.clone(),
+                _ if p.locked => String::from("locked"),
+                _ => return true,
+            };
+            info!("Skipping post {:?}, {}: {:?}", p.id, reason, p.title);
+            false
+        })
+        .collect()
+}
+
+/// Recognizes the megathreads, rules and announcements of moderators, which aren't jokes.
+///
+/// Everything is matched ignoring case.
+pub struct AnnouncementFilter {
+    /// Skip posts whose title contains any of these
+    pub title_phrases: Vec<String>,
+    /// Skip posts by these authors
+    pub authors: Vec<String>,
+    /// Skip posts whose flair contains any of these
+    pub flairs: Vec<String>,
+}
+
+impl AnnouncementFilter {
+    /// Why `post` is an announcement, or `None` if it isn't.
+    fn reason(&self, post: &RedditPost) -> Option<String> {
+        let author = post.author.to_lowercase();
+        if self.authors.contains(&author) {
+            return Some(format!("posted by {}", post.author));
+        }
+        let title = post.title.to_lowercase();
+        if let Some(phrase) = self
+            .title_phrases
+            .iter()
+            .find(|p| title.contains(p.as_str()))
+        {
+            return Some(format!("title contains {:?}", phrase));
+        }
+        let flair = post.flair.as_deref().unwrap_or_default().to_lowercase();
+        if let Some(skipped) = self.flairs.iter().find(|f| flair.contains(f.as_str())) {
+            return Some(format!("flaired {:?}", skipped));
+        }
+        None
+    }
+}
+
+/// Remove the posts that are announcements according to `filter`.
+pub fn filter_announcements(
+    posts: Vec<RedditPost>,
+    filter: &AnnouncementFilter,
+) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| match filter.reason(p) {
+            Some(reason) => {
+                debug!("Skipping announcement, {}: {:?}", reason, p);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// Remove link and image posts, and posts with an empty selftext.
+pub fn filter_without_selftext(posts: Vec<RedditPost>) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            if !p.is_self {
+                debug!("Skipping link post {:?}", p);
+                false
+            } else if p.selftext.trim().is_empty() {
+                debug!("Skipping post without selftext {:?}", p);
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+pub fn filter_by_min_score(posts: Vec<RedditPost>, min_score: i64) -> Vec<RedditPost> {
+    posts.into_iter().filter(|p| p.score >= min_score).collect()
+}
+
+/// Remove the posts with fewer than `min_comments` comments or a lower upvote ratio than
+/// `min_upvote_ratio`, which were upvoted early but not actually liked.
+pub fn filter_by_reception(
+    posts: Vec<RedditPost>,
+    min_comments: Option<u64>,
+    min_upvote_ratio: Option<f64>,
+) -> Vec<RedditPost> {
+    posts
+        .into_iter()
+        .filter(|p| {
+            let liked = min_comments.is_none_or(|min| p.num_comments >= min)
+                && min_upvote_ratio.is_none_or(|min| p.upvote_ratio >= min);
+            if !liked {
+                debug!(
+                    "Skipping post {:?} with {} comments and an upvote ratio of {:.2}",
+                    p.id, p.num_comments, p.upvote_ratio
+                );
+            }
+            liked
+        })
+        .collect()
+}
+
+/// Pick the post to tell from `posts`, by the `selection_mode` of `config`.
+pub fn select_post<'a, R: Rng>(
+    config: &RedditConfig,
+    posts: &'a [RedditPost],
+    rng: &mut R,
+    clock: &dyn Clock,
+) -> Option<&'a RedditPost> {
+    match config.selection_mode {
+        SelectionMode::Top => posts.iter().max_by_key(|p| p.score),
+        SelectionMode::Weighted => posts
+            .choose_weighted(rng, |p| (p.score as f64).max(config.selection_score_floor))
+            .ok(),
+        SelectionMode::Trending => {
+            let now = clock.unix_now() as i64;
+            let score = |p: &RedditPost| trending_score(p, now, config.trending_gravity);
+            posts
+                .iter()
+                .max_by(|a, b| score(a).partial_cmp(&score(b)).unwrap_or(Ordering::Equal))
+        }
+    }
+}
+
+/// The score of `post` at `now` in trending mode, like the ranking of Hacker News:
+/// `score / (age_hours + 2) ^ gravity`. The higher `gravity`, the sooner old posts drop.
+///
+/// Posts without a creation time are treated as brand new.
+fn trending_score(post: &RedditPost, now: i64, gravity: f64) -> f64 {
+    let age_secs = post.created_utc.map_or(0, |created| (now - created).max(0));
+    let age_hours = age_secs as f64 / 3600.0;
+    post.score as f64 / (age_hours + 2.0).powf(gravity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reddit_error_reads_error_responses() {
+        let json = json!({ "message": "Too Many Requests", "error": 429 });
+        assert_eq!(
+            reddit_error(&json).as_deref(),
+            Some("Too Many Requests (429)")
+        );
+        let json = json!({ "reason": "private", "message": "Forbidden", "error": 403 });
+        assert_eq!(reddit_error(&json).as_deref(), Some("Forbidden (403)"));
+        let json: Value =
+            serde_json::from_str(include_str!("../fixtures/reddit_feed.json")).unwrap();
+        assert_eq!(reddit_error(&json), None);
+    }
+
+    #[test]
+    fn parse_joke_file_reads_json_lines() {
+        let posts = parse_joke_file(
+            "{\"title\": \"Setup?\", \"selftext\": \"Punchline!\"}\n\n\
+             {\"id\": \"abc\", \"title\": \"Only a title\", \"score\": 5}\n",
+        )
+        .unwrap();
+        assert_eq!(posts.len(), 2);
+        assert!(posts[0].id.starts_with("file-"));
+        assert_eq!(posts[0].selftext, "Punchline!");
+        assert_eq!(posts[0].score, 1);
+        assert_eq!((posts[1].id.as_str(), posts[1].score), ("abc", 5));
+        assert_eq!(posts[1].selftext, "");
+
+        let again =
+            parse_joke_file("{\"title\": \"Setup?\", \"selftext\": \"Punchline!\"}").unwrap();
+        assert_eq!(again[0].id, posts[0].id);
+        assert!(parse_joke_file("not json").is_err());
+    }
+
+    #[test]
+    fn parse_joke_file_reads_arrays_and_listings() {
+        let posts = parse_joke_file("[{\"title\": \"a\"}, {\"title\": \"b\"}]").unwrap();
+        assert_eq!(posts.len(), 2);
+        assert_ne!(posts[0].id, posts[1].id);
+
+        let listing = parse_joke_file(include_str!("../fixtures/reddit_feed.json")).unwrap();
+        let json: Value =
+            serde_json::from_str(include_str!("../fixtures/reddit_feed.json")).unwrap();
+        assert_eq!(ids(&listing), ids(&parse_reddit_posts(&json)));
+    }
+
+    #[test]
+    fn filter_by_min_score_drops_low_scoring_posts() {
+        let json: Value =
+            serde_json::from_str(include_str!("../fixtures/reddit_mixed_scores.json")).unwrap();
+        let posts = filter_by_min_score(parse_reddit_posts(&json), 5);
+        let ids: Vec<&str> = posts.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["high", "exact"]);
+    }
+
+    #[test]
+    fn build_reddit_url_only_sends_time_for_top() {
+        let times = [
+            (TimeRange::Hour, "hour"),
+            (TimeRange::Day, "day"),
+            (TimeRange::Week, "week"),
+            (TimeRange::Month, "month"),
+            (TimeRange::Year, "year"),
+            (TimeRange::All, "all"),
+        ];
+        for &(time, name) in &times {
+            assert_eq!(
+                build_reddit_url("dadjokes", Listing::Top, time, 25),
+                format!(
+                    "https://www.reddit.com/r/dadjokes/top.json?t={}&limit=25",
+                    name
+                )
+            );
+            assert_eq!(
+                build_reddit_url("dadjokes", Listing::New, time, 25),
+                "https://www.reddit.com/r/dadjokes/new.json?limit=25"
+            );
+            assert_eq!(
+                build_reddit_url("dadjokes", Listing::Hot, time, 25),
+                "https://www.reddit.com/r/dadjokes/hot.json?limit=25"
+            );
+        }
+    }
+
+    #[test]
+    fn build_reddit_url_accepts_r_prefix() {
+        assert_eq!(
+            build_reddit_url("r/dadjokes", Listing::New, TimeRange::Day, 20),
+            "https://www.reddit.com/r/dadjokes/new.json?limit=20"
+        );
+    }
+
+    #[test]
+    fn build_reddit_url_accepts_multireddits() {
+        let url = "https://www.reddit.com/user/someone/m/cleanjokes/new.json?limit=20";
+        for multireddit in &[
+            "/user/someone/m/cleanjokes/",
+            "user/someone/m/cleanjokes",
+            "u/someone/m/cleanjokes",
+        ] {
+            assert_eq!(
+                build_reddit_url(multireddit, Listing::New, TimeRange::Day, 20),
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reddit_posts_keeps_subreddit_of_multireddit_posts() {
+        let posts = fixture(include_str!("../fixtures/reddit_multireddit.json"));
+        let subreddits: Vec<&str> = posts.iter().map(|p| p.subreddit.as_str()).collect();
+        assert_eq!(subreddits, vec!["dadjokes", "cleanjokes"]);
+    }
+
+    fn fixture(json: &str) -> Vec<RedditPost> {
+        parse_reddit_posts(&serde_json::from_str(json).unwrap())
+    }
+
+    fn ids(posts: &[RedditPost]) -> Vec<&str> {
+        posts.iter().map(|p| p.id.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_reddit_posts_reads_every_field() {
+        let posts = fixture(include_str!("../fixtures/reddit_feed.json"));
+        assert_eq!(ids(&posts), vec!["joke", "rules", "link"]);
+
+        let joke = &posts[0];
+        assert_eq!(joke.title, "What do you call a fish wearing a bowtie?");
+        assert_eq!(joke.selftext, "Sofishticated.");
+        assert_eq!(joke.score, 42);
+        assert_eq!(joke.subreddit, "dadjokes");
+        assert!(joke.is_self);
+        assert!(!joke.over_18 && !joke.spoiler && !joke.stickied);
+
+        assert_eq!((joke.num_comments, joke.upvote_ratio), (12, 0.93));
+        assert_eq!((posts[1].num_comments, posts[1].upvote_ratio), (0, 1.0));
+        assert_eq!(joke.created_utc, Some(1_560_000_000));
+        assert_eq!(posts[1].created_utc, None);
+
+        assert!(posts[1].stickied);
+        let link = &posts[2];
+        assert!(!link.is_self);
+        assert!(link.over_18 && link.spoiler);
+    }
+
+    #[test]
+    fn parse_reddit_posts_skips_post_without_selftext() {
+        let posts = fixture(include_str!("../fixtures/reddit_missing_selftext.json"));
+        assert_eq!(ids(&posts), vec!["complete"]);
+    }
+
+    #[test]
+    fn parse_reddit_posts_skips_post_with_string_score() {
+        let posts = fixture(include_str!("../fixtures/reddit_string_score.json"));
+        assert_eq!(ids(&posts), vec!["number"]);
+    }
+
+    #[test]
+    fn parse_top_comment_picks_highest_scoring_comment() {
+        let json: Value =
+            serde_json::from_str(include_str!("../fixtures/reddit_comments.json")).unwrap();
+        assert_eq!(
+            parse_top_comment(&json).as_deref(),
+            Some("Because it was two tired.")
+        );
+        assert_eq!(parse_top_comment(&serde_json::json!([{}, {}])), None);
+    }
+
+    #[test]
+    fn filter_by_reception_checks_comments_and_upvote_ratio() {
+        let post = |id: &str, num_comments: u64, upvote_ratio: f64| RedditPost {
+            id: String::from(id),
+            num_comments,
+            upvote_ratio,
+            ..RedditPost::default()
+        };
+        let posts = || {
+            vec![
+                post("liked", 10, 0.9),
+                post("quiet", 1, 0.95),
+                post("groan", 30, 0.6),
+            ]
+        };
+        assert_eq!(
+            ids(&filter_by_reception(posts(), Some(5), Some(0.8))),
+            vec!["liked"]
+        );
+        assert_eq!(
+            ids(&filter_by_reception(posts(), None, Some(0.8))),
+            vec!["liked", "quiet"]
+        );
+        assert_eq!(ids(&filter_by_reception(posts(), None, None)).len(), 3);
+    }
+
+    #[test]
+    fn trending_score_decays_with_age() {
+        let now = 1_560_000_000;
+        let post = |score, age_hours: i64| RedditPost {
+            score,
+            created_utc: Some(now - age_hours * 3600),
+            ..RedditPost::default()
+        };
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(
+            trending_score(&post(100, 0), now, 1.8),
+            100.0 / 2f64.powf(1.8)
+        ));
+        assert!(close(
+            trending_score(&post(100, 8), now, 1.8),
+            100.0 / 10f64.powf(1.8)
+        ));
+        assert!(close(trending_score(&post(100, 8), now, 0.0), 100.0));
+
+        // A fresh post beats an old one with a lot more upvotes
+        let fresh = trending_score(&post(50, 1), now, 1.8);
+        let stale = trending_score(&post(2000, 72), now, 1.8);
+        assert!(fresh > stale);
+
+        // Clocks that are a bit off don't make posts from the future score higher
+        let future = trending_score(&post(100, -5), now, 1.8);
+        assert!(close(future, trending_score(&post(100, 0), now, 1.8)));
+    }
+
+    #[test]
+    fn filter_removed_skips_removed_and_locked_posts() {
+        let post =
+            |id: &str, selftext: &str, locked, removed_by_category: Option<&str>| RedditPost {
+                id: String::from(id),
+                selftext: String::from(selftext),
+                locked,
+                removed_by_category: removed_by_category.map(String::from),
+                ..RedditPost::default()
+            };
+        let posts = vec![
+            post("fine", "Punchline", false, None),
+            post("moderator", "Punchline", false, Some("moderator")),
+            post("deleted", "[deleted]", false, None),
+            post("locked", "Punchline", true, None),
+        ];
+        assert_eq!(ids(&filter_removed(posts)), vec!["fine"]);
+    }
+
+    #[test]
+    fn filter_announcements_skips_authors_titles_and_flairs() {
+        let filter = AnnouncementFilter {
+            title_phrases: vec![String::from("megathread")],
+            authors: vec![String::from("automoderator")],
+            flairs: vec![String::from("meta")],
+        };
+        let post = |id: &str, title: &str, author: &str, flair: Option<&str>| RedditPost {
+            id: String::from(id),
+            title: String::from(title),
+            author: String::from(author),
+            flair: flair.map(String::from),
+            ..RedditPost::default()
+        };
+        let posts = vec![
+            post("bot", "Weekly thread", "AutoModerator", None),
+            post("thread", "Pun MEGATHREAD", "mod", None),
+            post("meta", "We have a new mod", "mod", Some("Meta Post")),
+            post("joke", "A joke", "dad", Some("Pun")),
+        ];
+        assert_eq!(ids(&filter_announcements(posts, &filter)), vec!["joke"]);
+    }
+
+    #[test]
+    fn parse_reddit_posts_handles_empty_listing() {
+        assert!(fixture(include_str!("../fixtures/reddit_empty.json")).is_empty());
+        assert!(fixture("{}").is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set when the program is shutting down, so long waits can be cut short.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Start shutting down, e.g. on Ctrl-C. The main loop stops and restores the terminal.
+pub fn request() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Whether the program is shutting down.
+pub fn is_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Resolves once the program is shutting down.
+pub async fn requested() {
+    while !is_requested() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Sleep for `duration`, returning early if the program is shutting down.
+pub async fn sleep(duration: Duration) {
+    sleep_with(duration, || {}).await
+}
+
+/// Like `sleep`, calling `on_tick` every 100ms while sleeping.
+pub async fn sleep_with(duration: Duration, mut on_tick: impl FnMut()) {
+    let step = Duration::from_millis(100);
+    let mut remaining = duration;
+    while remaining > Duration::from_secs(0) && !is_requested() {
+        on_tick();
+        let sleep = remaining.min(step);
+        tokio::time::sleep(sleep).await;
+        remaining -= sleep;
+    }
+}
@@ -0,0 +1,196 @@
+use log::{debug, info};
+use serde_json::{json, Value};
+
+/// Where the spacestate comes from, and how to tell whether the space is open
+pub struct SpacestateConfig {
+    pub url: &'static str,
+    pub format: SpacestateFormat,
+    /// `SPACESTATE_JSON_POINTER` with `SPACESTATE_OPEN_VALUE`, which are used instead of `format`
+    pub custom: Option<(&'static str, &'static str)>,
+    /// How many polls in a row the space has to be open before a joke is told
+    pub open_cycles_required: u32,
+}
+
+impl SpacestateConfig {
+    fn open_check(&self) -> OpenCheck {
+        match self.custom {
+            Some((pointer, open_value)) => OpenCheck {
+                pointer,
+                open_value: parse_open_value(open_value),
+            },
+            None => self.format.open_check(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpacestateFormat {
+    /// `{"state": "open"}`, as used by pixelbar
+    Pixelbar,
+    /// The SpaceAPI schema, see https://spaceapi.io/
+    SpaceApi,
+}
+
+impl SpacestateFormat {
+    fn open_check(self) -> OpenCheck {
+        match self {
+            SpacestateFormat::Pixelbar => OpenCheck {
+                pointer: "/state",
+                open_value: json!("open"),
+            },
+            SpacestateFormat::SpaceApi => OpenCheck {
+                pointer: "/state/open",
+                open_value: json!(true),
+            },
+        }
+    }
+}
+
+/// Where the state is in the spacestate response, and what it is when the space is open
+#[derive(Debug, Clone, PartialEq)]
+struct OpenCheck {
+    /// A json pointer like `/state/open`
+    pointer: &'static str,
+    open_value: Value,
+}
+
+/// `SPACESTATE_OPEN_VALUE` as json, like `true` or `1`, or otherwise as a string, so `open` doesn't
+/// need quotes.
+fn parse_open_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| json!(value))
+}
+
+pub async fn space_is_open(
+    client: &reqwest::Client,
+    config: &SpacestateConfig,
+) -> Result<bool, reqwest::Error> {
+    let response: Value = client.get(config.url).send().await?.json().await?;
+    Ok(parse_spacestate(&response, &config.open_check()))
+}
+
+/// Keeps track of how many consecutive polls the space has been open.
+#[derive(Debug)]
+pub struct OpenStreak {
+    cycles: u32,
+    required: u32,
+}
+
+impl OpenStreak {
+    /// A streak that is ready after `required` polls in a row.
+    pub fn new(required: u32) -> OpenStreak {
+        OpenStreak {
+            cycles: 0,
+            required,
+        }
+    }
+
+    /// Whether the space was open at the last poll.
+    pub fn is_open(&self) -> bool {
+        self.cycles > 0
+    }
+
+    /// Whether the space becomes ready if it is still open at the next poll, so the posts are worth
+    /// loading while checking.
+    pub fn ready_after_next_open(&self) -> bool {
+        self.is_open() && self.cycles.saturating_add(1) >= self.required
+    }
+
+    /// Record the state of the space at this poll.
+    ///
+    /// Returns `true` once the space has been open for the required polls in a row.
+    pub fn update(&mut self, open: bool) -> bool {
+        if open {
+            if self.cycles == 0 {
+                info!("Space is now open");
+            }
+            self.cycles = self.cycles.saturating_add(1);
+        } else {
+            if self.cycles > 0 {
+                info!("Space is now closed");
+            }
+            self.cycles = 0;
+        }
+        let ready = self.cycles >= self.required;
+        if open && !ready {
+            info!(
+                "Space has been open for {}/{} cycles, waiting before telling a joke",
+                self.cycles, self.required
+            );
+        }
+        ready
+    }
+}
+
+/// Whether the value at the pointer of `check` is its open value. A response without the value
+/// counts as closed.
+fn parse_spacestate(response: &Value, check: &OpenCheck) -> bool {
+    match response.pointer(check.pointer) {
+        Some(value) => *value == check.open_value,
+        None => {
+            debug!(
+                "Spacestate has nothing at {:?}: {}",
+                check.pointer, response
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_streak_is_ready_after_the_required_polls() {
+        let mut streak = OpenStreak::new(3);
+        assert!(!streak.ready_after_next_open());
+        assert!(!streak.update(true));
+        assert!(!streak.ready_after_next_open());
+        assert!(!streak.update(true));
+        assert!(streak.ready_after_next_open());
+        assert!(streak.update(true));
+        assert!(!streak.update(false));
+        assert!(!streak.ready_after_next_open());
+    }
+
+    #[test]
+    fn parse_spacestate_checks_the_formats() {
+        let pixelbar = SpacestateFormat::Pixelbar.open_check();
+        assert!(parse_spacestate(&json!({"state": "open"}), &pixelbar));
+        assert!(!parse_spacestate(&json!({"state": "closed"}), &pixelbar));
+        assert!(!parse_spacestate(&json!({"state": true}), &pixelbar));
+
+        let spaceapi = SpacestateFormat::SpaceApi.open_check();
+        assert!(parse_spacestate(
+            &json!({"space": "Pixelbar", "state": {"open": true}}),
+            &spaceapi
+        ));
+        assert!(!parse_spacestate(
+            &json!({"state": {"open": false}}),
+            &spaceapi
+        ));
+        assert!(!parse_spacestate(&json!({"state": "open"}), &spaceapi));
+    }
+
+    #[test]
+    fn parse_spacestate_follows_custom_pointers() {
+        let check = OpenCheck {
+            pointer: "/sensors/0/door",
+            open_value: parse_open_value("unlocked"),
+        };
+        let response = |door| json!({"sensors": [{"door": door}]});
+        assert!(parse_spacestate(&response("unlocked"), &check));
+        assert!(!parse_spacestate(&response("locked"), &check));
+        assert!(!parse_spacestate(&json!({"sensors": []}), &check));
+
+        let check = OpenCheck {
+            pointer: "/status/is_open",
+            open_value: parse_open_value("1"),
+        };
+        assert!(parse_spacestate(&json!({"status": {"is_open": 1}}), &check));
+        assert!(!parse_spacestate(
+            &json!({"status": {"is_open": "1"}}),
+            &check
+        ));
+    }
+}
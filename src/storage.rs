@@ -0,0 +1,767 @@
+use crate::clock::{Clock, SystemClock};
+use crate::reddit::RedditPost;
+use anyhow::Context;
+use chrono::TimeZone;
+use log::debug;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UsedIdsBackend {
+    /// Store the used ids in `USED_IDS_FILE`, one id per line
+    File,
+    /// Store the used ids in the sqlite database at `USED_IDS_DB`
+    Sqlite,
+}
+
+/// How the told jokes are remembered
+pub struct UsedIdsConfig {
+    pub backend: UsedIdsBackend,
+    /// `USED_IDS_FILE` or `USED_IDS_DB`, depending on the backend
+    pub path: &'static str,
+    /// How long a joke is remembered, `None` for forever
+    pub ttl: Option<Duration>,
+    /// Most jokes to remember, 0 for no limit
+    pub max: usize,
+    /// Also count posts with the title of a told joke as told
+    pub dedupe_by_title: bool,
+}
+
+/// Open the used jokes store of `config`
+pub fn open(config: &UsedIdsConfig) -> Result<Box<dyn UsedJokes>, anyhow::Error> {
+    let path = config.path;
+    Ok(match config.backend {
+        UsedIdsBackend::File => Box::new(
+            FileUsedJokes::load(path, config.ttl, Box::new(SystemClock))
+                .with_context(|| format!("Could not read USED_IDS_FILE {:?}", path))?,
+        ),
+        UsedIdsBackend::Sqlite => {
+            let used_jokes = SqliteUsedJokes::open(path, config.ttl, Box::new(SystemClock))
+                .with_context(|| format!("Could not open USED_IDS_DB {:?}", path))?;
+            Box::new(used_jokes)
+        }
+    })
+}
+
+/// Create the directory of `path` if it doesn't exist, and check that the file can be written,
+/// so a wrong path fails at startup instead of in every cycle.
+///
+/// The file is created if it doesn't exist yet, but never truncated.
+pub fn ensure_file_writable(path: &str) -> Result<(), anyhow::Error> {
+    if let Some(dir) = Path::new(path).parent() {
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create the directory of {:?}", path))?;
+        }
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Could not write {:?}", path))?;
+    Ok(())
+}
+
+/// Replace the file at `path` with `contents`.
+///
+/// The contents are written to a temporary file next to it first, so a crash halfway through
+/// leaves the old file intact instead of a truncated one.
+pub fn replace_file(path: &str, contents: &[u8]) -> Result<(), anyhow::Error> {
+    let temp_path = format!("{}.tmp", path);
+    let mut output =
+        File::create(&temp_path).with_context(|| format!("Could not open {:?}", temp_path))?;
+    output
+        .write_all(contents)
+        .and_then(|()| output.sync_all())
+        .with_context(|| format!("Could not write {:?}", temp_path))?;
+    std::fs::rename(&temp_path, path).with_context(|| format!("Could not replace {:?}", path))?;
+    Ok(())
+}
+
+/// Like `ensure_file_writable`, but for a directory that files are written in.
+pub fn ensure_dir_writable(dir: &str) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Could not create {:?}", dir))?;
+    let probe = Path::new(dir).join(".dadjokes-write-test");
+    File::create(&probe).with_context(|| format!("Could not write in {:?}", dir))?;
+    // Only the check matters, a leftover probe file is harmless
+    let _ = std::fs::remove_file(probe);
+    Ok(())
+}
+
+/// The last few jokes told since the program started, so a small pool of jokes doesn't repeat the
+/// same ones over and over when the used ids don't rule them out.
+pub struct RecentJokes {
+    /// Most recent last
+    ids: VecDeque<String>,
+    window: usize,
+}
+
+impl RecentJokes {
+    /// Remember the last `window` jokes, 0 to not remember any.
+    pub fn new(window: usize) -> RecentJokes {
+        RecentJokes {
+            ids: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    pub fn record(&mut self, id: &str) {
+        if self.window == 0 {
+            return;
+        }
+        self.ids.retain(|recent| recent != id);
+        if self.ids.len() == self.window {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(id.to_owned());
+    }
+
+    /// Remove the recently told posts. When every post was told recently, only the one that was
+    /// told the longest ago is kept, so there is always something to tell.
+    pub fn filter(&self, posts: Vec<RedditPost>) -> Vec<RedditPost> {
+        let position = |post: &RedditPost| self.ids.iter().position(|id| *id == post.id);
+        if posts.iter().any(|post| position(post).is_none()) {
+            return posts
+                .into_iter()
+                .filter(|post| {
+                    let recent = position(post).is_some();
+                    if recent {
+                        debug!("Skipping recently told post {:?}", post.id);
+                    }
+                    !recent
+                })
+                .collect();
+        }
+        posts.into_iter().min_by_key(position).into_iter().collect()
+    }
+}
+
+/// Keeps track of which jokes have already been told.
+pub trait UsedJokes {
+    fn contains(&self, id: &str) -> Result<bool, anyhow::Error>;
+    /// Whether a joke with this title, normalized with `normalize_title`, has been told
+    fn contains_title(&self, title: &str) -> Result<bool, anyhow::Error>;
+    fn record(&mut self, post: &RedditPost) -> Result<(), anyhow::Error>;
+    /// The unix timestamp of when the most recent joke was told, `None` if none was told yet
+    fn last_told_at(&self) -> Result<Option<u64>, anyhow::Error>;
+    /// Forget the jokes that may be told again after the TTL, and all but the `max` most recently
+    /// told ones if `max` isn't 0. Returns how many jokes were forgotten.
+    fn compact(&mut self, max: usize) -> Result<usize, anyhow::Error>;
+}
+
+/// Remove the posts that have already been told.
+///
+/// With `dedupe_by_title`, posts with the same title as a told joke count as told too, so reposts
+/// aren't told again.
+pub fn filter_untold(
+    posts: Vec<RedditPost>,
+    used_jokes: &dyn UsedJokes,
+    dedupe_by_title: bool,
+) -> Result<Vec<RedditPost>, anyhow::Error> {
+    let mut result = Vec::with_capacity(posts.len());
+    for post in posts {
+        if used_jokes.contains(&post.id)? {
+            continue;
+        }
+        if dedupe_by_title {
+            let title = normalize_title(&post.title);
+            // A title of only emoji or punctuation says nothing about the joke
+            if !title.is_empty() && used_jokes.contains_title(&title)? {
+                debug!("Skipping repost of a told joke {:?}", post);
+                continue;
+            }
+        }
+        result.push(post);
+    }
+    Ok(result)
+}
+
+/// `title` in lowercase, without punctuation and whitespace, so differently formatted copies of a
+/// title are the same.
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Whether a joke was told earlier today according to `clock`, in local time.
+pub fn told_today(used_jokes: &dyn UsedJokes, clock: &dyn Clock) -> Result<bool, anyhow::Error> {
+    Ok(match used_jokes.last_told_at()? {
+        Some(told_at) => {
+            let today = clock.now().with_timezone(&chrono::Local).date();
+            chrono::Local.timestamp(told_at as i64, 0).date() == today
+        }
+        None => false,
+    })
+}
+
+/// A joke id, the unix timestamp of when it was told and its normalized title.
+#[derive(Debug, Clone, PartialEq)]
+struct UsedId {
+    id: String,
+    told_at: u64,
+    /// Empty for jokes that were told before titles were tracked
+    title: String,
+}
+
+impl UsedId {
+    /// Parse a line in the form of `id<TAB>unix_ts<TAB>normalized_title`.
+    ///
+    /// Lines written before timestamps were tracked only contain the id, these are treated as told
+    /// at `loaded_at`, so a TTL configured later starts counting when they are first loaded.
+    fn parse(line: &str, loaded_at: u64) -> Option<UsedId> {
+        let mut parts = line.splitn(3, '\t');
+        let id = parts.next()?.trim();
+        if id.is_empty() {
+            return None;
+        }
+        let told_at = parts
+            .next()
+            .and_then(|ts| ts.trim().parse().ok())
+            .unwrap_or(loaded_at);
+        let title = parts.next().unwrap_or_default().trim();
+        Some(UsedId {
+            id: id.to_owned(),
+            told_at,
+            title: title.to_owned(),
+        })
+    }
+
+    fn is_expired(&self, ttl: Option<Duration>, now: u64) -> bool {
+        match ttl {
+            Some(ttl) => self.told_at + ttl.as_secs() <= now,
+            None => false,
+        }
+    }
+}
+
+/// Remove the `ids` that expired after `ttl`, and all but the `max` most recently told ones if
+/// `max` isn't 0. Returns how many ids were removed.
+fn compact_ids(ids: &mut Vec<UsedId>, ttl: Option<Duration>, max: usize, now: u64) -> usize {
+    let before = ids.len();
+    ids.retain(|used| !used.is_expired(ttl, now));
+    if max > 0 && ids.len() > max {
+        ids.sort_by_key(|used| used.told_at);
+        ids.drain(..ids.len() - max);
+    }
+    before - ids.len()
+}
+
+/// Stores the used ids in a text file, one `id<TAB>unix_ts<TAB>normalized_title` entry per line.
+struct FileUsedJokes {
+    path: String,
+    ids: Vec<UsedId>,
+    ttl: Option<Duration>,
+    clock: Box<dyn Clock>,
+}
+
+impl FileUsedJokes {
+    /// Fails if the file exists but can't be read, as saving it would throw away the ids in it.
+    fn load(
+        path: &str,
+        ttl: Option<Duration>,
+        clock: Box<dyn Clock>,
+    ) -> std::io::Result<FileUsedJokes> {
+        let ids = match load_used_ids(path, clock.unix_now()) {
+            Ok(ids) => ids,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(FileUsedJokes {
+            path: path.to_owned(),
+            ids,
+            ttl,
+            clock,
+        })
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let mut contents = String::new();
+        for used in &self.ids {
+            contents.push_str(&format!("{}\t{}\t{}\n", used.id, used.told_at, used.title));
+        }
+        replace_file(&self.path, contents.as_bytes()).context("Could not save USED_IDS_FILE")?;
+        Ok(())
+    }
+}
+
+impl UsedJokes for FileUsedJokes {
+    fn contains(&self, id: &str) -> Result<bool, anyhow::Error> {
+        let now = self.clock.unix_now();
+        Ok(self
+            .ids
+            .iter()
+            .any(|used| used.id == id && !used.is_expired(self.ttl, now)))
+    }
+
+    fn contains_title(&self, title: &str) -> Result<bool, anyhow::Error> {
+        let now = self.clock.unix_now();
+        Ok(self
+            .ids
+            .iter()
+            .any(|used| used.title == title && !used.is_expired(self.ttl, now)))
+    }
+
+    fn record(&mut self, post: &RedditPost) -> Result<(), anyhow::Error> {
+        let told_at = self.clock.unix_now();
+        let title = normalize_title(&post.title);
+        match self.ids.iter_mut().find(|used| used.id == post.id) {
+            Some(used) => {
+                used.told_at = told_at;
+                used.title = title;
+            }
+            None => self.ids.push(UsedId {
+                id: post.id.clone(),
+                told_at,
+                title,
+            }),
+        }
+        self.save()
+    }
+
+    fn last_told_at(&self) -> Result<Option<u64>, anyhow::Error> {
+        Ok(self.ids.iter().map(|used| used.told_at).max())
+    }
+
+    fn compact(&mut self, max: usize) -> Result<usize, anyhow::Error> {
+        let pruned = compact_ids(&mut self.ids, self.ttl, max, self.clock.unix_now());
+        if pruned > 0 {
+            self.save()?;
+        }
+        Ok(pruned)
+    }
+}
+
+fn load_used_ids(path: &str, loaded_at: u64) -> std::io::Result<Vec<UsedId>> {
+    let file = File::open(path)?;
+    let mut ids = Vec::new();
+    for line in BufReader::new(file).split(b'\n') {
+        // A line that isn't valid UTF-8 is only skipped, so the ids after it aren't lost
+        if let Ok(line) = String::from_utf8(line?) {
+            ids.extend(UsedId::parse(&line, loaded_at));
+        }
+    }
+    Ok(ids)
+}
+
+/// Stores the used ids, along with the title, normalized title and when they were told, in a sqlite
+/// database.
+struct SqliteUsedJokes {
+    connection: rusqlite::Connection,
+    ttl: Option<Duration>,
+    clock: Box<dyn Clock>,
+}
+
+impl SqliteUsedJokes {
+    fn open(
+        path: &str,
+        ttl: Option<Duration>,
+        clock: Box<dyn Clock>,
+    ) -> Result<SqliteUsedJokes, anyhow::Error> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS used_jokes (
+                id TEXT PRIMARY KEY NOT NULL,
+                title TEXT NOT NULL,
+                told_at INTEGER NOT NULL,
+                normalized_title TEXT
+            )",
+            [],
+        )?;
+        // Databases made before titles were deduplicated don't have the column yet
+        if connection
+            .prepare("SELECT normalized_title FROM used_jokes LIMIT 0")
+            .is_err()
+        {
+            connection.execute(
+                "ALTER TABLE used_jokes ADD COLUMN normalized_title TEXT",
+                [],
+            )?;
+        }
+        Ok(SqliteUsedJokes {
+            connection,
+            ttl,
+            clock,
+        })
+    }
+}
+
+impl SqliteUsedJokes {
+    /// Whether a joke for which `column` is `value` was told within the TTL
+    fn contains_where(&self, column: &str, value: &str) -> Result<bool, anyhow::Error> {
+        let told_after = match self.ttl {
+            Some(ttl) => self.clock.unix_now().saturating_sub(ttl.as_secs()) as i64,
+            None => i64::MIN,
+        };
+        let count: i64 = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM used_jokes WHERE {} = ?1 AND told_at > ?2",
+                    column
+                ),
+                rusqlite::params![value, told_after],
+                |row| row.get(0),
+            )
+            .context("Could not query USED_IDS_DB")?;
+        Ok(count > 0)
+    }
+}
+
+impl UsedJokes for SqliteUsedJokes {
+    fn contains(&self, id: &str) -> Result<bool, anyhow::Error> {
+        self.contains_where("id", id)
+    }
+
+    fn contains_title(&self, title: &str) -> Result<bool, anyhow::Error> {
+        self.contains_where("normalized_title", title)
+    }
+
+    fn record(&mut self, post: &RedditPost) -> Result<(), anyhow::Error> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO used_jokes (id, title, told_at, normalized_title)
+                 VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![
+                    post.id,
+                    post.title,
+                    self.clock.unix_now() as i64,
+                    normalize_title(&post.title)
+                ],
+            )
+            .context("Could not save USED_IDS_DB")?;
+        Ok(())
+    }
+
+    fn last_told_at(&self) -> Result<Option<u64>, anyhow::Error> {
+        let told_at: Option<i64> = self
+            .connection
+            .query_row("SELECT MAX(told_at) FROM used_jokes", [], |row| row.get(0))
+            .context("Could not query USED_IDS_DB")?;
+        Ok(told_at.map(|told_at| told_at as u64))
+    }
+
+    fn compact(&mut self, max: usize) -> Result<usize, anyhow::Error> {
+        let mut pruned = 0;
+        if let Some(ttl) = self.ttl {
+            let told_after = self.clock.unix_now().saturating_sub(ttl.as_secs()) as i64;
+            pruned += self
+                .connection
+                .execute(
+                    "DELETE FROM used_jokes WHERE told_at <= ?1",
+                    rusqlite::params![told_after],
+                )
+                .context("Could not compact USED_IDS_DB")?;
+        }
+        if max > 0 {
+            pruned += self
+                .connection
+                .execute(
+                    "DELETE FROM used_jokes WHERE id NOT IN
+                     (SELECT id FROM used_jokes ORDER BY told_at DESC LIMIT ?1)",
+                    rusqlite::params![max as i64],
+                )
+                .context("Could not compact USED_IDS_DB")?;
+        }
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use std::path::Path;
+
+    /// The time the tests run at
+    const NOW: u64 = 1_560_000_000;
+
+    fn clock() -> Box<dyn Clock> {
+        Box::new(FixedClock(chrono::Utc.timestamp(NOW as i64, 0)))
+    }
+
+    fn file_used_jokes(ids: Vec<UsedId>, ttl_days: u64) -> FileUsedJokes {
+        FileUsedJokes {
+            path: String::new(),
+            ids,
+            ttl: Some(Duration::from_secs(ttl_days * 24 * 60 * 60)),
+            clock: clock(),
+        }
+    }
+
+    fn post(id: &str) -> RedditPost {
+        RedditPost {
+            id: String::from(id),
+            ..RedditPost::default()
+        }
+    }
+
+    #[test]
+    fn filter_untold_removes_every_told_post() {
+        let used_jokes = FileUsedJokes {
+            path: String::new(),
+            ids: ["a", "b"]
+                .iter()
+                .map(|id| UsedId {
+                    id: String::from(*id),
+                    told_at: NOW,
+                    title: String::new(),
+                })
+                .collect(),
+            ttl: None,
+            clock: clock(),
+        };
+        let untold = filter_untold(vec![post("a"), post("b")], &used_jokes, false).unwrap();
+        assert!(untold.is_empty());
+
+        let untold = filter_untold(vec![post("a"), post("c")], &used_jokes, false).unwrap();
+        let ids: Vec<&str> = untold.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["c"]);
+    }
+
+    #[test]
+    fn file_used_jokes_replaces_file_and_keeps_ids_after_bad_lines() {
+        let dir = std::env::temp_dir().join(format!("dadjokes-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("used_ids.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"old\t1560000000\n\xff\xfe\nafter\t1560000000\n").unwrap();
+
+        let mut used_jokes = FileUsedJokes::load(path, None, clock()).unwrap();
+        used_jokes.record(&post("new")).unwrap();
+
+        let reloaded = FileUsedJokes::load(path, None, clock()).unwrap();
+        assert!(reloaded.contains("old").unwrap());
+        assert!(reloaded.contains("after").unwrap());
+        assert!(reloaded.contains("new").unwrap());
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recent_jokes_are_skipped_until_the_pool_runs_out() {
+        let pool = || vec![post("a"), post("b"), post("c")];
+        let ids = |posts: Vec<RedditPost>| posts.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        let mut recent = RecentJokes::new(2);
+        assert_eq!(ids(recent.filter(pool())), vec!["a", "b", "c"]);
+
+        recent.record("a");
+        recent.record("b");
+        assert_eq!(ids(recent.filter(pool())), vec!["c"]);
+
+        // Only two are remembered, so telling c makes a available again
+        recent.record("c");
+        assert_eq!(ids(recent.filter(pool())), vec!["a"]);
+
+        // With every post told recently, the one told the longest ago wraps around
+        let small_pool = vec![post("b"), post("c")];
+        assert_eq!(ids(recent.filter(small_pool)), vec!["b"]);
+        recent.record("b");
+        assert_eq!(ids(recent.filter(vec![post("b"), post("c")])), vec!["c"]);
+
+        let none = RecentJokes::new(0);
+        assert_eq!(ids(none.filter(pool())).len(), 3);
+    }
+
+    #[test]
+    fn ensure_file_writable_creates_parent_dirs() {
+        let dir = std::env::temp_dir().join(format!("dadjokes-dirs-{}", std::process::id()));
+        let path = dir.join("nested/used_ids.txt");
+        let path = path.to_str().unwrap();
+        ensure_file_writable(path).unwrap();
+        std::fs::write(path, "old\t1560000000\n").unwrap();
+        ensure_file_writable(path).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "old\t1560000000\n");
+
+        let archive = dir.join("archive");
+        ensure_dir_writable(archive.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_dir(&archive).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn titled_post(id: &str, title: &str) -> RedditPost {
+        RedditPost {
+            title: String::from(title),
+            ..post(id)
+        }
+    }
+
+    #[test]
+    fn normalize_title_ignores_case_punctuation_and_whitespace() {
+        assert_eq!(
+            normalize_title("Why did the Scarecrow win an award?"),
+            normalize_title("why did the scarecrow  win an award")
+        );
+        assert_eq!(normalize_title("  I'm   Hungry!! "), "imhungry");
+        assert_ne!(normalize_title("Dad joke"), normalize_title("Bad joke"));
+    }
+
+    #[test]
+    fn filter_untold_dedupes_reposts_by_title() {
+        let used_jokes = file_used_jokes(
+            vec![UsedId {
+                id: String::from("original"),
+                told_at: NOW,
+                title: normalize_title("What do you call a fake noodle?"),
+            }],
+            7,
+        );
+
+        let posts = vec![
+            titled_post("repost", "what do you call a FAKE noodle"),
+            titled_post("other", "What do you call a fake spaghetti?"),
+        ];
+        let untold = filter_untold(posts.clone(), &used_jokes, true).unwrap();
+        let ids: Vec<&str> = untold.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["other"]);
+
+        let untold = filter_untold(posts, &used_jokes, false).unwrap();
+        assert_eq!(untold.len(), 2);
+    }
+
+    #[test]
+    fn used_id_parses_title() {
+        assert_eq!(
+            UsedId::parse("abc123\t1560000000\tfakenoodle", NOW),
+            Some(UsedId {
+                id: String::from("abc123"),
+                told_at: 1_560_000_000,
+                title: String::from("fakenoodle"),
+            })
+        );
+    }
+
+    #[test]
+    fn used_id_parses_lines_with_and_without_timestamp() {
+        assert_eq!(
+            UsedId::parse("abc123\t1500000000", NOW),
+            Some(UsedId {
+                id: String::from("abc123"),
+                told_at: 1_500_000_000,
+                title: String::new(),
+            })
+        );
+        assert_eq!(
+            UsedId::parse("abc123", NOW),
+            Some(UsedId {
+                id: String::from("abc123"),
+                told_at: NOW,
+                title: String::new(),
+            })
+        );
+        assert_eq!(UsedId::parse("", NOW), None);
+    }
+
+    #[test]
+    fn legacy_ids_are_still_suppressed_after_a_ttl_is_set() {
+        let dir = std::env::temp_dir().join(format!("dadjokes-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("used_ids.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "legacy\n").unwrap();
+
+        let ttl = Some(Duration::from_secs(7 * 24 * 60 * 60));
+        let used_jokes = FileUsedJokes::load(path, ttl, clock()).unwrap();
+        assert!(used_jokes.contains("legacy").unwrap());
+        assert_eq!(used_jokes.last_told_at().unwrap(), Some(NOW));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_joke_can_be_told_again() {
+        let told_at = NOW - 10 * 24 * 60 * 60;
+        let used_jokes = file_used_jokes(
+            vec![UsedId {
+                id: String::from("old"),
+                told_at,
+                title: String::new(),
+            }],
+            7,
+        );
+        assert!(!used_jokes.contains("old").unwrap());
+    }
+
+    #[test]
+    fn fresh_joke_is_suppressed() {
+        let told_at = NOW - 24 * 60 * 60;
+        let used_jokes = file_used_jokes(
+            vec![UsedId {
+                id: String::from("fresh"),
+                told_at,
+                title: String::new(),
+            }],
+            7,
+        );
+        assert!(used_jokes.contains("fresh").unwrap());
+    }
+
+    #[test]
+    fn told_today_compares_local_dates() {
+        let noon = chrono::Local.ymd(2019, 6, 1).and_hms(12, 0, 0);
+        let clock = FixedClock(noon.with_timezone(&chrono::Utc));
+        let told_at = |at: chrono::DateTime<chrono::Local>| {
+            file_used_jokes(
+                vec![UsedId {
+                    id: String::from("joke"),
+                    told_at: at.timestamp() as u64,
+                    title: String::new(),
+                }],
+                7,
+            )
+        };
+        let this_morning = chrono::Local.ymd(2019, 6, 1).and_hms(8, 0, 0);
+        let last_night = chrono::Local.ymd(2019, 5, 31).and_hms(23, 0, 0);
+        assert!(told_today(&told_at(this_morning), &clock).unwrap());
+        assert!(!told_today(&told_at(last_night), &clock).unwrap());
+        assert!(!told_today(&file_used_jokes(Vec::new(), 7), &clock).unwrap());
+    }
+
+    #[test]
+    fn compact_ids_drops_expired_and_oldest_ids() {
+        let day = 24 * 60 * 60;
+        let used = |id: &str, told_at: u64| UsedId {
+            id: String::from(id),
+            told_at,
+            title: String::new(),
+        };
+        let mut ids = vec![
+            used("ancient", 0),
+            used("expired", NOW - 10 * day),
+            used("old", NOW - 3 * day),
+            used("new", NOW - day),
+            used("older", NOW - 4 * day),
+        ];
+        let ttl = Some(Duration::from_secs(7 * day));
+        assert_eq!(compact_ids(&mut ids, ttl, 2, NOW), 3);
+        let kept: Vec<&str> = ids.iter().map(|used| used.id.as_str()).collect();
+        assert_eq!(kept, vec!["old", "new"]);
+
+        let mut ids = vec![used("ancient", 0), used("new", NOW)];
+        assert_eq!(compact_ids(&mut ids, None, 0, NOW), 0);
+        assert_eq!(ids.len(), 2);
+    }
+
+    #[test]
+    fn sqlite_compact_keeps_the_most_recent_jokes() {
+        let mut used_jokes =
+            SqliteUsedJokes::open(":memory:", Some(Duration::from_secs(60)), clock()).unwrap();
+        for (id, told_at) in &[("expired", NOW - 60), ("old", NOW - 30), ("new", NOW - 10)] {
+            used_jokes
+                .connection
+                .execute(
+                    "INSERT INTO used_jokes (id, title, told_at) VALUES (?1, '', ?2)",
+                    rusqlite::params![id, *told_at as i64],
+                )
+                .unwrap();
+        }
+        assert_eq!(used_jokes.compact(1).unwrap(), 2);
+        assert!(used_jokes.contains("new").unwrap());
+        assert!(!used_jokes.contains("old").unwrap());
+        assert_eq!(used_jokes.compact(1).unwrap(), 0);
+    }
+}
@@ -0,0 +1,1565 @@
+use crate::abbreviations::Abbreviations;
+use crate::error::AppError;
+use crate::http::Http;
+use crate::reddit::RedditPost;
+use crate::{audio, health, metrics, proxy, shutdown, storage};
+use anyhow::{bail, Context};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::Regex;
+use rusoto_core::{HttpClient, Region, RusotoError};
+use rusoto_credential::StaticProvider;
+use rusoto_polly::{Polly, PollyClient};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+lazy_static! {
+    /// Urls with a scheme or starting with `www.`, without the punctuation after them
+    static ref URL: Regex =
+        Regex::new(r"(?i)([ \t]*)\b(?:https?://|www\.)[^\s<>()\[\]]*[^\s<>()\[\].,:;!?'\x22]").unwrap();
+}
+
+/// How the jokes are read out
+pub struct TtsConfig {
+    pub aws_polly_access_key: &'static str,
+    pub aws_polly_secret_access_key: &'static str,
+    pub aws_region: Region,
+    pub engine: PollyEngine,
+    pub language_code: &'static str,
+    /// Always read with this voice, instead of a random one
+    pub voice_id: Option<&'static str>,
+    /// Only read with voices of this gender, `None` for any gender
+    pub voice_gender: Option<&'static str>,
+    /// How likely the voices are to be picked, see `parse_voice_weights`
+    pub voice_weights: Option<Vec<(&'static str, u32)>>,
+    pub output_format: OutputFormat,
+    pub use_ssml: bool,
+    /// Read before every joke
+    pub intro_text: Option<&'static str>,
+    pub prosody: Prosody,
+    pub spoken_urls: UrlMode,
+    /// Keep the synthesized audio in `audio_cache_dir`, so a joke is only paid for once
+    pub cache_audio: bool,
+    pub audio_cache_dir: &'static str,
+    /// Where the synthesized characters are counted
+    pub stats_file: &'static str,
+    /// The price in dollars of a million characters, for the estimated cost
+    pub cost_per_million: f64,
+    pub long_text_mode: LongTextMode,
+    /// The longest joke with the truncate `long_text_mode`
+    pub max_text_chars: usize,
+    /// How many times a throttled request is tried, including the first time
+    pub retries: u32,
+    /// Read the joke with `fallback_command` when Polly fails
+    pub fallback: bool,
+    pub fallback_command: &'static str,
+}
+
+/// What happens to the urls in a joke
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlMode {
+    /// Replace every url with the word "link"
+    Link,
+    Remove,
+    /// Leave the urls alone, Polly spells them out
+    Keep,
+}
+
+/// The SSML break before the punchline when `PUNCHLINE_DELAY_MS` is not set
+pub const DEFAULT_PUNCHLINE_BREAK: Duration = Duration::from_millis(700);
+/// The silence between `INTRO_TEXT` and the joke
+const INTRO_PAUSE: Duration = Duration::from_millis(500);
+
+/// The most characters Polly synthesizes in a single request
+const POLLY_MAX_CHARS: usize = 3000;
+
+/// How jokes that are too long to be read in one go are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LongTextMode {
+    /// Cut the joke off at `MAX_TEXT_CHARS`
+    Truncate,
+    /// Synthesize the joke in parts of at most `POLLY_MAX_CHARS` and play them back to back
+    Chunk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PollyEngine {
+    Standard,
+    Neural,
+}
+
+impl PollyEngine {
+    fn as_str(self) -> &'static str {
+        match self {
+            PollyEngine::Standard => "standard",
+            PollyEngine::Neural => "neural",
+        }
+    }
+}
+
+/// The formats Polly can synthesize audio in, except for speech marks which can't be played
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Mp3,
+    OggVorbis,
+    /// Raw samples at `PCM_SAMPLE_RATE`
+    Pcm,
+}
+
+/// The sample rate of `OutputFormat::Pcm` audio
+const PCM_SAMPLE_RATE: u32 = 16000;
+
+impl OutputFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::OggVorbis => "ogg_vorbis",
+            OutputFormat::Pcm => "pcm",
+        }
+    }
+
+    /// The extension of files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::OggVorbis => "ogg",
+            OutputFormat::Pcm => "pcm",
+        }
+    }
+
+    fn encoding(self) -> audio::Encoding {
+        match self {
+            OutputFormat::Mp3 | OutputFormat::OggVorbis => audio::Encoding::File,
+            // Polly sends 16-bit signed little-endian mono samples
+            OutputFormat::Pcm => audio::Encoding::Pcm {
+                sample_rate: PCM_SAMPLE_RATE,
+            },
+        }
+    }
+}
+
+/// A Polly client. When `proxy_url` or the environment sets a proxy, its requests are sent with
+/// `http` so they go through it too.
+pub fn new_client(config: &TtsConfig, http: &Http, proxy_url: Option<&str>) -> PollyClient {
+    let credentials = StaticProvider::new_minimal(
+        config.aws_polly_access_key.to_string(),
+        config.aws_polly_secret_access_key.to_string(),
+    );
+    let region = config.aws_region.clone();
+    if proxy::proxy_in_use(proxy_url).is_some() {
+        let dispatcher = proxy::ReqwestDispatcher {
+            client: http.client.clone(),
+        };
+        PollyClient::new_with(dispatcher, credentials, region)
+    } else {
+        PollyClient::new_with(
+            HttpClient::new().expect("Could not make http client"),
+            credentials,
+            region,
+        )
+    }
+}
+
+/// Get the voices that can be used for `POLLY_LANGUAGE_CODE` and `POLLY_ENGINE`.
+///
+/// This is the first call to Polly, so it also checks the AWS credentials. Network errors are
+/// retried, but rejected credentials fail immediately.
+pub async fn load_voices(
+    config: &TtsConfig,
+    http: &Http,
+    client: &PollyClient,
+) -> Result<Vec<rusoto_polly::Voice>, anyhow::Error> {
+    let voices = describe_voices(config, http, client).await?;
+    let voices = filter_voices_by_gender(voices, config.voice_gender, config.language_code)?;
+    Ok(filter_voices_by_engine(voices, config.engine))
+}
+
+/// Only keep the voices of `gender`, like `male`, ignoring case. Fails if there are none.
+fn filter_voices_by_gender(
+    voices: Vec<rusoto_polly::Voice>,
+    gender: Option<&str>,
+    language_code: &str,
+) -> Result<Vec<rusoto_polly::Voice>, anyhow::Error> {
+    let gender = match gender {
+        Some(gender) => gender,
+        None => return Ok(voices),
+    };
+    let is_gender = |voice: &rusoto_polly::Voice| {
+        voice
+            .gender
+            .as_deref()
+            .is_some_and(|g| g.eq_ignore_ascii_case(gender))
+    };
+    if !voices.iter().any(is_gender) {
+        return Err(AppError::Config(format!(
+            "Polly has no {} voices for language {:?}, check VOICE_GENDER and POLLY_LANGUAGE_CODE",
+            gender, language_code
+        ))
+        .into());
+    }
+    Ok(voices.into_iter().filter(is_gender).collect())
+}
+
+/// Every voice of `POLLY_LANGUAGE_CODE`, whatever engines they support.
+pub async fn describe_voices(
+    config: &TtsConfig,
+    http: &Http,
+    client: &PollyClient,
+) -> Result<Vec<rusoto_polly::Voice>, anyhow::Error> {
+    let input = rusoto_polly::DescribeVoicesInput {
+        language_code: Some(config.language_code.to_string()),
+        ..Default::default()
+    };
+    let output = http
+        .with_retries("describe voices", || {
+            let request = http.with_timeout(client.describe_voices(input.clone()));
+            async move {
+                match request.await {
+                    // Wrong keys won't fix themselves, so there is no point in retrying
+                    Err(e) if is_auth_error(&e) => Ok(Err(e)),
+                    result => result.map(Ok),
+                }
+            }
+        })
+        .await
+        .context("Could not describe voices")?;
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => bail!("AWS Polly authentication failed — check your keys: {}", e),
+    };
+    let voices = output.voices.unwrap_or_default();
+    if voices.is_empty() {
+        bail!(
+            "Polly has no voices for language {:?}, check POLLY_LANGUAGE_CODE",
+            config.language_code
+        );
+    }
+    Ok(voices)
+}
+
+/// A table of the id, gender and supported engines of every voice, for `--list-voices`.
+pub fn format_voices(voices: &[rusoto_polly::Voice]) -> String {
+    let rows: Vec<[String; 3]> = voices
+        .iter()
+        .map(|voice| {
+            [
+                voice.id.clone().unwrap_or_default(),
+                voice.gender.clone().unwrap_or_default(),
+                voice
+                    .supported_engines
+                    .as_deref()
+                    .unwrap_or_default()
+                    .join(", "),
+            ]
+        })
+        .collect();
+    let header = [
+        String::from("Voice"),
+        String::from("Gender"),
+        String::from("Engines"),
+    ];
+    let width = |column: usize| {
+        std::iter::once(&header)
+            .chain(&rows)
+            .map(|row| row[column].chars().count())
+            .max()
+            .unwrap_or_default()
+    };
+    let (id_width, gender_width) = (width(0), width(1));
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|[id, gender, engines]| {
+            format!(
+                "{:id_width$}  {:gender_width$}  {}\n",
+                id,
+                gender,
+                engines,
+                id_width = id_width,
+                gender_width = gender_width
+            )
+        })
+        .collect()
+}
+
+/// Whether `error` means AWS rejected our credentials, rather than a temporary problem.
+fn is_auth_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<RusotoError<rusoto_polly::DescribeVoicesError>>() {
+        Some(RusotoError::Credentials(_)) => true,
+        Some(RusotoError::Unknown(response)) => matches!(response.status.as_u16(), 401 | 403),
+        _ => false,
+    }
+}
+
+/// Whether Polly refused `error` because too many requests are made, which is worth retrying.
+fn is_throttling_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<RusotoError<rusoto_polly::SynthesizeSpeechError>>() {
+        Some(RusotoError::Unknown(response)) => {
+            response.status.as_u16() == 429 || response.body_as_str().contains("Throttling")
+        }
+        _ => false,
+    }
+}
+
+/// Parse a list of voices with their weight like `Matthew=3,Joanna=2`.
+pub fn parse_voice_weights(weights: &str) -> Result<Vec<(&str, u32)>, String> {
+    weights
+        .split(',')
+        .map(str::trim)
+        .filter(|weight| !weight.is_empty())
+        .map(|weight| {
+            let mut parts = weight.splitn(2, '=');
+            let voice = parts.next().unwrap_or_default().trim();
+            match parts.next().map(|w| w.trim().parse()) {
+                Some(Ok(w)) if w > 0 && !voice.is_empty() => Ok((voice, w)),
+                _ => Err(format!(
+                    "{:?} is not a voice with a positive weight like Matthew=3",
+                    weight
+                )),
+            }
+        })
+        .collect()
+}
+
+/// The voices jokes are read with, and how likely every one of them is to be picked.
+pub struct Voices {
+    voices: Vec<rusoto_polly::Voice>,
+    /// The index of the voice configured by `POLLY_VOICE_ID`, if any
+    pinned: Option<usize>,
+    /// The index of every voice that can be picked with its weight, all voices are equally likely
+    /// unless `VOICE_WEIGHTS` is set
+    weighted: Vec<(usize, u32)>,
+}
+
+impl Voices {
+    /// Fails when a voice of `POLLY_VOICE_ID` or `VOICE_WEIGHTS` is not one of `voices`.
+    pub fn new(
+        voices: Vec<rusoto_polly::Voice>,
+        config: &TtsConfig,
+    ) -> Result<Voices, anyhow::Error> {
+        Voices::with_choice(voices, config.voice_id, config.voice_weights.as_deref())
+    }
+
+    fn with_choice(
+        voices: Vec<rusoto_polly::Voice>,
+        pinned: Option<&str>,
+        weights: Option<&[(&str, u32)]>,
+    ) -> Result<Voices, anyhow::Error> {
+        let find = |name: &str, voice_id: &str| match voices
+            .iter()
+            .position(|v| v.id.as_deref() == Some(voice_id))
+        {
+            Some(index) => Ok(index),
+            None => Err(AppError::Config(format!(
+                "{} {:?} is not an available voice, expected one of {:?}",
+                name,
+                voice_id,
+                voices
+                    .iter()
+                    .filter_map(|v| v.id.as_ref())
+                    .collect::<Vec<_>>()
+            ))),
+        };
+        let pinned = match pinned {
+            Some(voice_id) => Some(find("POLLY_VOICE_ID", voice_id)?),
+            None => None,
+        };
+        let weighted = match weights {
+            Some(weights) => weights
+                .iter()
+                .map(|&(voice_id, weight)| Ok((find("VOICE_WEIGHTS voice", voice_id)?, weight)))
+                .collect::<Result<_, anyhow::Error>>()?,
+            None => (0..voices.len()).map(|index| (index, 1)).collect(),
+        };
+        Ok(Voices {
+            voices,
+            pinned,
+            weighted,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// The pinned voice, or otherwise a random voice by weight.
+    pub fn pick(&self, rng: &mut impl Rng) -> &rusoto_polly::Voice {
+        let index = match self.pinned {
+            Some(index) => index,
+            None => {
+                self.weighted
+                    .choose_weighted(rng, |&(_, weight)| weight)
+                    .unwrap()
+                    .0
+            }
+        };
+        &self.voices[index]
+    }
+}
+
+fn voice_supports_engine(voice: &rusoto_polly::Voice, engine: PollyEngine) -> bool {
+    match &voice.supported_engines {
+        Some(engines) => engines.iter().any(|e| e == engine.as_str()),
+        // Polly only started reporting engines when neural voices were introduced
+        None => engine == PollyEngine::Standard,
+    }
+}
+
+/// Only keep the voices that support `engine`.
+///
+/// If no voice supports `engine`, all voices are kept and will fall back to the standard engine.
+fn filter_voices_by_engine(
+    voices: Vec<rusoto_polly::Voice>,
+    engine: PollyEngine,
+) -> Vec<rusoto_polly::Voice> {
+    let supported: Vec<_> = voices
+        .iter()
+        .filter(|voice| voice_supports_engine(voice, engine))
+        .cloned()
+        .collect();
+    if supported.is_empty() {
+        warn!(
+            "No voices support the {} engine, falling back to the standard engine",
+            engine.as_str()
+        );
+        voices
+    } else {
+        supported
+    }
+}
+
+/// The engine to use for `voice`, falling back to the standard engine if it does not support
+/// `engine`.
+pub fn engine_for_voice(voice: &rusoto_polly::Voice, engine: PollyEngine) -> PollyEngine {
+    if engine != PollyEngine::Standard && !voice_supports_engine(voice, engine) {
+        info!(
+            "Voice {:?} does not support the {} engine, falling back to the standard engine",
+            voice.id,
+            engine.as_str()
+        );
+        PollyEngine::Standard
+    } else {
+        engine
+    }
+}
+
+/// How fast and how high the jokes are read with SSML, `None` for the default of the voice
+pub struct Prosody {
+    /// Like `slow` or `90%`
+    pub rate: Option<&'static str>,
+    /// Like `low` or `-10%`
+    pub pitch: Option<&'static str>,
+}
+
+/// Whether Polly accepts `rate` as the rate of a `<prosody>` tag, a keyword or a percentage of
+/// the normal speed from 20% to 200%.
+pub fn is_speech_rate(rate: &str) -> bool {
+    const KEYWORDS: &[&str] = &["x-slow", "slow", "medium", "fast", "x-fast"];
+    KEYWORDS.contains(&rate)
+        || rate
+            .strip_suffix('%')
+            .and_then(|percentage| percentage.parse::<f64>().ok())
+            .is_some_and(|percentage| (20.0..=200.0).contains(&percentage))
+}
+
+/// Whether Polly accepts `pitch` as the pitch of a `<prosody>` tag, a keyword or a relative change
+/// like `+5%` or `-10%`.
+pub fn is_speech_pitch(pitch: &str) -> bool {
+    const KEYWORDS: &[&str] = &["default", "x-low", "low", "medium", "high", "x-high"];
+    KEYWORDS.contains(&pitch)
+        || (pitch.starts_with(&['+', '-'][..])
+            && pitch[1..]
+                .strip_suffix('%')
+                .and_then(|percentage| percentage.parse::<f64>().ok())
+                .is_some_and(f64::is_finite))
+}
+
+/// The text of a joke as it is sent to Polly
+pub struct SpeechText {
+    text: String,
+    ssml: bool,
+    /// Silence to play before this text, for the pause before the punchline without SSML
+    pause_before: Duration,
+}
+
+impl SpeechText {
+    /// Combine the title and selftext of a joke, with the `abbreviations` read out in full. When
+    /// `ssml` is set, a `pause` is added between the two for comedic timing.
+    pub fn new(
+        title: &str,
+        selftext: &str,
+        ssml: bool,
+        pause: Duration,
+        abbreviations: &Abbreviations,
+    ) -> SpeechText {
+        if ssml {
+            let ssml_text = |text| abbreviations.to_ssml(text);
+            if let (Some(title), Some(selftext)) = (ssml_text(title), ssml_text(selftext)) {
+                return SpeechText {
+                    text: format!(
+                        "<speak>{}<break time=\"{}ms\"/>{}</speak>",
+                        title,
+                        pause.as_millis(),
+                        selftext
+                    ),
+                    ssml: true,
+                    pause_before: Duration::from_secs(0),
+                };
+            }
+            warn!("Could not escape joke for SSML, falling back to plain text");
+        }
+        SpeechText::plain(format!(
+            "{}\n\n{}",
+            abbreviations.expand(title),
+            abbreviations.expand(selftext)
+        ))
+    }
+
+    /// Like `new`, but split into parts that are each short enough for Polly to synthesize.
+    ///
+    /// The selftext is split on sentence boundaries, the title is only read in the first part.
+    /// When a `pause` is set without SSML, the title is a part of its own so the pause can be
+    /// played as silence between it and the selftext. With SSML the pause is a break, of
+    /// `DEFAULT_PUNCHLINE_BREAK` if it is not set.
+    ///
+    /// Plain text is measured with the abbreviations expanded, as that is what Polly gets.
+    pub fn chunked(
+        title: &str,
+        selftext: &str,
+        ssml: bool,
+        pause: Option<Duration>,
+        abbreviations: &Abbreviations,
+    ) -> Vec<SpeechText> {
+        let (title, selftext) = if ssml {
+            (title.to_owned(), selftext.to_owned())
+        } else {
+            (abbreviations.expand(title), abbreviations.expand(selftext))
+        };
+        // Plain text is already expanded, expanding it again could replace parts of expansions
+        let part = |text: &str| {
+            if ssml {
+                SpeechText::continuation(text, true, abbreviations)
+            } else {
+                SpeechText::plain(text.to_owned())
+            }
+        };
+        let split = |pause: &Duration| {
+            !ssml && *pause > Duration::from_secs(0) && !selftext.trim().is_empty()
+        };
+        if let Some(pause) = pause.filter(split) {
+            let mut result = vec![part(&title)];
+            result.extend(
+                split_into_chunks(&selftext, POLLY_MAX_CHARS)
+                    .iter()
+                    .map(|chunk| part(chunk)),
+            );
+            if let Some(punchline) = result.get_mut(1) {
+                punchline.pause_before = pause;
+            }
+            return result;
+        }
+        let pause = pause.unwrap_or(DEFAULT_PUNCHLINE_BREAK);
+        let whole = |selftext: &str| {
+            if ssml {
+                SpeechText::new(&title, selftext, true, pause, abbreviations)
+            } else {
+                SpeechText::plain(format!("{}\n\n{}", title, selftext))
+            }
+        };
+        let title_len = title.chars().count() + 2;
+        if title_len + selftext.chars().count() <= POLLY_MAX_CHARS {
+            return vec![whole(&selftext)];
+        }
+        let max = POLLY_MAX_CHARS.saturating_sub(title_len).max(1);
+        let mut chunks = split_into_chunks(&selftext, max).into_iter();
+        let mut result = vec![whole(&chunks.next().unwrap_or_default())];
+        result.extend(chunks.map(|chunk| part(&chunk)));
+        info!(
+            "Joke is too long for a single request, split it in {} parts",
+            result.len()
+        );
+        result
+    }
+
+    /// Read the text with `prosody`, when it is SSML. Plain text can't change the rate or pitch.
+    ///
+    /// The values of `prosody` have been validated, so they can't break out of the attributes.
+    pub fn with_prosody(mut self, prosody: &Prosody) -> SpeechText {
+        let mut attributes = String::new();
+        if let Some(rate) = prosody.rate {
+            attributes.push_str(&format!(" rate=\"{}\"", rate));
+        }
+        if let Some(pitch) = prosody.pitch {
+            attributes.push_str(&format!(" pitch=\"{}\"", pitch));
+        }
+        if !self.ssml || attributes.is_empty() {
+            return self;
+        }
+        let inner = self
+            .text
+            .trim_start_matches("<speak>")
+            .trim_end_matches("</speak>");
+        self.text = format!("<speak><prosody{}>{}</prosody></speak>", attributes, inner);
+        self
+    }
+
+    /// `texts` with `intro` read as a part of its own before them, followed by a short pause.
+    ///
+    /// As its own part the intro doesn't count towards the length of the joke, so it is never cut
+    /// off with the joke and never makes the joke be split.
+    pub fn with_intro(
+        mut texts: Vec<SpeechText>,
+        intro: &str,
+        ssml: bool,
+        abbreviations: &Abbreviations,
+    ) -> Vec<SpeechText> {
+        if intro.trim().is_empty() {
+            return texts;
+        }
+        if let Some(first) = texts.first_mut() {
+            first.pause_before = INTRO_PAUSE;
+        }
+        texts.insert(0, SpeechText::continuation(intro, ssml, abbreviations));
+        texts
+    }
+
+    /// How long to wait after the previous part before playing this one
+    pub fn pause_before(&self) -> Duration {
+        self.pause_before
+    }
+
+    /// A part of the selftext that is read after the first part.
+    fn continuation(text: &str, ssml: bool, abbreviations: &Abbreviations) -> SpeechText {
+        if ssml {
+            if let Some(text) = abbreviations.to_ssml(text) {
+                return SpeechText {
+                    text: format!("<speak>{}</speak>", text),
+                    ssml: true,
+                    pause_before: Duration::from_secs(0),
+                };
+            }
+            warn!("Could not escape joke for SSML, falling back to plain text");
+        }
+        SpeechText::plain(abbreviations.expand(text))
+    }
+
+    /// Plain `text` that is read as it is.
+    fn plain(text: String) -> SpeechText {
+        SpeechText {
+            text,
+            ssml: false,
+            pause_before: Duration::from_secs(0),
+        }
+    }
+
+    fn text_type(&self) -> &'static str {
+        if self.ssml {
+            "ssml"
+        } else {
+            "text"
+        }
+    }
+}
+
+/// Split `text` on sentence boundaries into chunks of at most `max` characters.
+///
+/// Sentences that are longer than `max` are split on word boundaries, and words that are longer
+/// than `max` are cut.
+fn split_into_chunks(text: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for piece in sentences(text).flat_map(|sentence| split_sentence(sentence, max)) {
+        let len = piece.chars().count();
+        if current_len > 0 && current_len + 1 + len > max {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if current_len > 0 {
+            current.push(' ');
+            current_len += 1;
+        }
+        current.push_str(&piece);
+        current_len += len;
+    }
+    if current_len > 0 {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// The sentences in `text`, which end in `.`, `!` or `?` followed by whitespace, or at a newline.
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        if c == '\n' || (c.is_whitespace() && ['.', '!', '?'].contains(&previous)) {
+            sentences.push(&text[start..i]);
+            start = i;
+        }
+        previous = c;
+    }
+    sentences.push(&text[start..]);
+    sentences
+        .into_iter()
+        .map(str::trim)
+        .filter(|sentence| !sentence.is_empty())
+}
+
+/// Split a `sentence` that is longer than `max` characters on word boundaries.
+fn split_sentence(sentence: &str, max: usize) -> Vec<String> {
+    if sentence.chars().count() <= max {
+        return vec![sentence.to_owned()];
+    }
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for word in sentence.split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+        for part in chars.chunks(max) {
+            // Counted in characters like `max`, `part` is a slice of them
+            let len = part.len();
+            if current_len > 0 && current_len + 1 + len > max {
+                pieces.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            if current_len > 0 {
+                current.push(' ');
+                current_len += 1;
+            }
+            current.extend(part);
+            current_len += len;
+        }
+    }
+    if current_len > 0 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Escape `text` so it can be embedded in an XML document.
+///
+/// Returns `None` if `text` contains characters that are not allowed in XML at all.
+pub fn escape_xml(text: &str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' => result.push_str("&amp;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            '\t' | '\n' | '\r' => result.push(c),
+            c if c.is_control() => return None,
+            c => result.push(c),
+        }
+    }
+    Some(result)
+}
+
+/// The audio of (a part of) a joke, ready to be played
+pub struct Speech {
+    /// Shared, so it can be played, kept for a replay and archived without copying it
+    pub audio: Arc<[u8]>,
+    pub encoding: audio::Encoding,
+    /// Set when Polly failed and the audio was made by `TTS_FALLBACK_COMMAND` instead
+    pub fallback: bool,
+}
+
+/// Get the speech for `text` from Polly, or from `TTS_FALLBACK_COMMAND` if Polly fails and
+/// `TTS_FALLBACK` is set.
+pub async fn load_speech(
+    config: &TtsConfig,
+    http: &Http,
+    client: &PollyClient,
+    voice_id: &str,
+    engine: PollyEngine,
+    text: &SpeechText,
+) -> Result<Speech, anyhow::Error> {
+    match load_polly_speech(config, http, client, voice_id, engine, text).await {
+        Ok(audio) => {
+            info!("Spoken by Polly voice {}", voice_id);
+            Ok(Speech {
+                audio: audio.into(),
+                encoding: config.output_format.encoding(),
+                fallback: false,
+            })
+        }
+        Err(e) if config.fallback => {
+            warn!(
+                "Could not get speech from Polly, falling back to {}: {:#}",
+                config.fallback_command, e
+            );
+            let audio = local_speech(config.fallback_command, text).await?;
+            info!("Spoken by {}", config.fallback_command);
+            Ok(Speech {
+                audio: audio.into(),
+                encoding: audio::Encoding::File,
+                fallback: true,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Synthesize `text` as wav with `fallback_command`, which has to accept the arguments of espeak.
+async fn local_speech(fallback_command: &str, text: &SpeechText) -> Result<Vec<u8>, anyhow::Error> {
+    let mut command = tokio::process::Command::new(fallback_command);
+    command.arg("--stdout");
+    if text.ssml {
+        command.arg("-m");
+    }
+    let output = command
+        .arg("--")
+        .arg(&text.text)
+        .output()
+        .await
+        .with_context(|| format!("Could not run {}", fallback_command))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed with {}: {}",
+            fallback_command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Get the speech for `text`, either from the audio cache or by synthesizing it with Polly.
+async fn load_polly_speech(
+    config: &TtsConfig,
+    http: &Http,
+    client: &PollyClient,
+    voice_id: &str,
+    engine: PollyEngine,
+    text: &SpeechText,
+) -> Result<Vec<u8>, anyhow::Error> {
+    if !config.cache_audio {
+        return synthesize_speech(config, http, client, voice_id, engine, text).await;
+    }
+
+    let path = audio_cache_path(config, voice_id, engine, &text.text);
+    match std::fs::read(&path) {
+        Ok(audio) => {
+            info!("Using cached audio {:?}", path);
+            return Ok(audio);
+        }
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Could not read cached audio {:?}: {:?}", path, e);
+        }
+        Err(_) => {}
+    }
+
+    let audio = synthesize_speech(config, http, client, voice_id, engine, text).await?;
+    if let Err(e) =
+        std::fs::create_dir_all(config.audio_cache_dir).and_then(|_| std::fs::write(&path, &audio))
+    {
+        warn!("Could not write cached audio {:?}: {:?}", path, e);
+    }
+    Ok(audio)
+}
+
+async fn synthesize_speech(
+    config: &TtsConfig,
+    http: &Http,
+    client: &PollyClient,
+    voice_id: &str,
+    engine: PollyEngine,
+    text: &SpeechText,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let input = rusoto_polly::SynthesizeSpeechInput {
+        engine: Some(String::from(engine.as_str())),
+        output_format: String::from(config.output_format.as_str()),
+        sample_rate: match config.output_format {
+            OutputFormat::Pcm => Some(PCM_SAMPLE_RATE.to_string()),
+            _ => None,
+        },
+        text: text.text.clone(),
+        text_type: Some(String::from(text.text_type())),
+        voice_id: voice_id.to_owned(),
+        ..Default::default()
+    };
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    let result = loop {
+        metrics::increment(&metrics::POLLY_REQUESTS);
+        let result = http
+            .with_timeout(client.synthesize_speech(input.clone()))
+            .await;
+        if result.is_err() {
+            metrics::increment(&metrics::POLLY_ERRORS);
+        }
+        match result {
+            // Anything else, like invalid SSML or wrong keys, fails the same way when retried
+            Err(e)
+                if is_throttling_error(&e)
+                    && attempt < config.retries
+                    && !shutdown::is_requested() =>
+            {
+                // Jitter, so several bots sharing an account don't all retry at the same time
+                let jittered = delay.mul_f64(rand::thread_rng().gen_range(0.5, 1.5));
+                warn!(
+                    "Polly is throttling requests (attempt {}/{}), retrying in {:?}: {}",
+                    attempt, config.retries, jittered, e
+                );
+                shutdown::sleep(jittered).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+    health::polly_called(result.is_ok());
+    let result = result.context("Could not synthesize speech")?;
+    record_usage(config, text.text.chars().count() as u64);
+    match result.audio_stream {
+        Some(stream) => Ok(stream.to_vec()),
+        None => bail!("Polly did not return any audio"),
+    }
+}
+
+/// Check that `POLLY_STATS_FILE` and, with `CACHE_AUDIO`, `AUDIO_CACHE_DIR` can be written.
+pub fn check_writable(config: &TtsConfig) -> Result<(), anyhow::Error> {
+    storage::ensure_file_writable(config.stats_file)?;
+    if config.cache_audio {
+        storage::ensure_dir_writable(config.audio_cache_dir)?;
+    }
+    Ok(())
+}
+
+/// Add `characters` to the total in `POLLY_STATS_FILE` and log the estimated cost so far.
+///
+/// SSML tags are counted as well, even though Polly doesn't bill them, so the estimate is on the
+/// high side.
+fn record_usage(config: &TtsConfig, characters: u64) {
+    let path = config.stats_file;
+    let previous = match std::fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse().unwrap_or_else(|_| {
+            warn!("Could not parse {:?}, starting over", path);
+            0
+        }),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => {
+            warn!("Could not read {:?}: {:?}", path, e);
+            0
+        }
+    };
+    let total: u64 = previous + characters;
+    if let Err(e) = std::fs::write(path, format!("{}\n", total)) {
+        warn!("Could not save {:?}: {:?}", path, e);
+    }
+    info!(
+        "Synthesized {} characters, {} in total, costing about ${:.2}",
+        characters,
+        total,
+        total as f64 / 1_000_000.0 * config.cost_per_million
+    );
+}
+
+/// The path of the cached audio for `text` spoken by `voice_id` in `OUTPUT_FORMAT`
+fn audio_cache_path(
+    config: &TtsConfig,
+    voice_id: &str,
+    engine: PollyEngine,
+    text: &str,
+) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(voice_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(engine.as_str().as_bytes());
+    hasher.update([0]);
+    hasher.update(text.as_bytes());
+    let extension = config.output_format.extension();
+    Path::new(config.audio_cache_dir).join(format!("{:x}.{}", hasher.finalize(), extension))
+}
+
+/// Shorten `post` so its title and selftext together are at most `max` characters long.
+///
+/// The selftext is cut off first, on a word boundary, with an ellipsis to show the joke goes on.
+pub fn truncate_joke(post: &RedditPost, max: usize) -> RedditPost {
+    let title_len = post.title.chars().count();
+    let selftext_len = post.selftext.chars().count();
+    if title_len + selftext_len <= max {
+        return post.clone();
+    }
+    info!(
+        "Joke is {} characters long, truncating it to {}",
+        title_len + selftext_len,
+        max
+    );
+    let mut post = post.clone();
+    if title_len >= max {
+        post.title = truncate_at_word(&post.title, max);
+        post.selftext = String::new();
+    } else {
+        post.selftext = truncate_at_word(&post.selftext, max - title_len);
+    }
+    post
+}
+
+/// Cut `text` off at the last word boundary that leaves room for an ellipsis in `max` characters.
+fn truncate_at_word(text: &str, max: usize) -> String {
+    const ELLIPSIS: &str = "...";
+    let max = max.saturating_sub(ELLIPSIS.len());
+    let end = text
+        .char_indices()
+        .nth(max)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let cut = &text[..end];
+    // Only break on a word boundary if there is one, otherwise cut the word itself
+    let cut = match text[end..].chars().next() {
+        Some(c) if !c.is_whitespace() => match cut.rfind(char::is_whitespace) {
+            Some(i) => &cut[..i],
+            None => cut,
+        },
+        _ => cut,
+    };
+    format!("{}{}", cut.trim_end(), ELLIPSIS)
+}
+
+/// Turn reddit markdown into plain text that Polly can read out loud.
+///
+/// This unescapes html entities, strips the common markdown markers and collapses repeated blank
+/// lines. Anything that isn't recognized as markdown is left alone.
+pub fn clean_selftext(text: &str) -> String {
+    // Reddit escapes the markdown source, which itself may contain entities like `&#x200B;`
+    let text = unescape_html_entities(&unescape_html_entities(text));
+    let mut result = String::with_capacity(text.len());
+    let mut previous_was_blank = true;
+
+    for line in text.lines() {
+        let line = strip_markdown(strip_markdown_line_prefix(line));
+        let line = line.trim();
+        if line.is_empty() {
+            if !previous_was_blank {
+                result.push('\n');
+            }
+            previous_was_blank = true;
+        } else {
+            result.push_str(line);
+            result.push('\n');
+            previous_was_blank = false;
+        }
+    }
+
+    result.trim_end().to_owned()
+}
+
+/// `clean_selftext`, with the urls handled according to `mode`.
+pub fn spoken_text(text: &str, mode: UrlMode) -> String {
+    strip_urls(&clean_selftext(text), mode)
+}
+
+/// Replace or remove the urls in `text`, which Polly would otherwise read one character at a time.
+pub fn strip_urls(text: &str, mode: UrlMode) -> String {
+    match mode {
+        UrlMode::Link => URL.replace_all(text, "${1}link").into_owned(),
+        UrlMode::Remove => {
+            let text = URL.replace_all(text, "");
+            // Removing a url at the start of a line leaves the space that came after it
+            text.lines()
+                .map(str::trim_start)
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        UrlMode::Keep => text.to_owned(),
+    }
+}
+
+fn unescape_html_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut remaining = text;
+
+    while let Some(start) = remaining.find('&') {
+        result.push_str(&remaining[..start]);
+        remaining = &remaining[start..];
+        let decoded = remaining.find(';').and_then(|end| {
+            let entity = &remaining[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                }
+                _ if entity.starts_with('#') => {
+                    entity[1..].parse().ok().and_then(std::char::from_u32)
+                }
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                // zero-width spaces are used by reddit users to force empty paragraphs
+                if c != '\u{200B}' {
+                    result.push(c);
+                }
+                remaining = &remaining[end + 1..];
+            }
+            None => {
+                result.push('&');
+                remaining = &remaining[1..];
+            }
+        }
+    }
+    result.push_str(remaining);
+    result
+}
+
+/// Strip block-level markdown like headers, quotes and list bullets from the start of a line
+fn strip_markdown_line_prefix(line: &str) -> &str {
+    let mut line = line.trim_start();
+    loop {
+        let stripped = if let Some(quoted) = line.strip_prefix('>') {
+            quoted
+        } else if line.starts_with('#') {
+            line.trim_start_matches('#')
+        } else if let Some(item) = line.strip_prefix("* ").or_else(|| line.strip_prefix("- ")) {
+            item
+        } else {
+            return line;
+        };
+        line = stripped.trim_start();
+    }
+}
+
+/// Strip inline markdown like emphasis, code and links from a line
+fn strip_markdown(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(&(_, next)) if next.is_ascii_punctuation() => {
+                    result.push(next);
+                    chars.next();
+                }
+                _ => result.push('\\'),
+            },
+            '*' | '`' | '~' | '_' => match parse_markdown_span(line, index) {
+                Some((delimiter, text)) => {
+                    if delimiter == "`" {
+                        result.push_str(text);
+                    } else {
+                        result.push_str(&strip_markdown(text));
+                    }
+                    let end = index + 2 * delimiter.len() + text.len();
+                    while chars.peek().is_some_and(|&(i, _)| i < end) {
+                        chars.next();
+                    }
+                }
+                None => result.push(c),
+            },
+            '[' => match parse_markdown_link(&line[index..]) {
+                Some((text, length)) => {
+                    result.push_str(&strip_markdown(text));
+                    while chars.peek().is_some_and(|&(i, _)| i < index + length) {
+                        chars.next();
+                    }
+                }
+                None => result.push('['),
+            },
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// The delimiters of inline markdown, longest first so `**` isn't taken for two `*`
+const MARKDOWN_DELIMITERS: &[&str] = &["**", "~~", "__", "*", "`"];
+
+/// Parse emphasis, strikethrough or code starting at `index` of `line`, returning the delimiter and
+/// the text between it and the closing one.
+///
+/// Only delimiters around text that doesn't start or end with a space, and that aren't part of a
+/// word count, so `2 * 3` and `f*ck` are left alone.
+fn parse_markdown_span(line: &str, index: usize) -> Option<(&'static str, &str)> {
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    if is_word(line[..index].chars().next_back()) {
+        return None;
+    }
+    MARKDOWN_DELIMITERS
+        .iter()
+        .filter(|delimiter| line[index..].starts_with(**delimiter))
+        .find_map(|&delimiter| {
+            let rest = &line[index + delimiter.len()..];
+            rest.match_indices(delimiter)
+                .map(|(end, _)| (&rest[..end], &rest[end + delimiter.len()..]))
+                .find(|(text, after)| {
+                    !text.is_empty()
+                        && !text.starts_with(char::is_whitespace)
+                        && !text.ends_with(char::is_whitespace)
+                        && !is_word(after.chars().next())
+                })
+                .map(|(text, _)| (delimiter, text))
+        })
+}
+
+/// Parse a markdown link in the form of `[text](url)`, returning the text and the length of the
+/// full link
+fn parse_markdown_link(text: &str) -> Option<(&str, usize)> {
+    let text_end = text.find("](")?;
+    let url_end = text[text_end..].find(')')? + text_end;
+    Some((&text[1..text_end], url_end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pause between the setup and the punchline in the speech text tests
+    const PAUSE: Duration = Duration::from_millis(700);
+
+    /// No abbreviations, so the texts are read as they are
+    fn no_abbreviations() -> Abbreviations {
+        Abbreviations::from_table("").unwrap()
+    }
+
+    fn setup_and_punchline(ssml: bool, pause: Option<Duration>) -> Vec<SpeechText> {
+        SpeechText::chunked("Setup?", "Punchline!", ssml, pause, &no_abbreviations())
+    }
+
+    #[test]
+    fn speech_text_escapes_ssml() {
+        let text = SpeechText::new(
+            "Tom & \"Jerry\"",
+            "<b>it's</b>",
+            true,
+            PAUSE,
+            &no_abbreviations(),
+        );
+        assert_eq!(
+            text.text,
+            "<speak>Tom &amp; &quot;Jerry&quot;<break time=\"700ms\"/>\
+             &lt;b&gt;it&apos;s&lt;/b&gt;</speak>"
+        );
+        assert!(text.ssml);
+
+        let text = SpeechText::new("Bad \u{1}", "text", true, PAUSE, &no_abbreviations());
+        assert_eq!(text.text, "Bad \u{1}\n\ntext");
+        assert!(!text.ssml);
+    }
+
+    #[test]
+    fn with_intro_reads_intro_first() {
+        let texts = setup_and_punchline(true, Some(PAUSE));
+        let texts = SpeechText::with_intro(texts, "Joke time & such:", true, &no_abbreviations());
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[0].text, "<speak>Joke time &amp; such:</speak>");
+        assert_eq!(texts[1].pause_before(), INTRO_PAUSE);
+        assert!(texts[1].text.starts_with("<speak>Setup?"));
+
+        let texts = setup_and_punchline(false, Some(PAUSE));
+        let texts = SpeechText::with_intro(texts, "Joke time:", false, &no_abbreviations());
+        let parts: Vec<_> = texts
+            .iter()
+            .map(|text| (text.text.as_str(), text.pause_before()))
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                ("Joke time:", Duration::from_secs(0)),
+                ("Setup?", INTRO_PAUSE),
+                ("Punchline!", PAUSE)
+            ]
+        );
+
+        let texts = setup_and_punchline(true, Some(PAUSE));
+        assert_eq!(
+            SpeechText::with_intro(texts, " ", true, &no_abbreviations()).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn with_prosody_wraps_ssml() {
+        let prosody = Prosody {
+            rate: Some("90%"),
+            pitch: Some("-5%"),
+        };
+        let text = SpeechText::new("Setup?", "Punchline!", true, PAUSE, &no_abbreviations())
+            .with_prosody(&prosody);
+        assert_eq!(
+            text.text,
+            "<speak><prosody rate=\"90%\" pitch=\"-5%\">Setup?<break time=\"700ms\"/>\
+             Punchline!</prosody></speak>"
+        );
+
+        let text = SpeechText::new("Setup?", "Punchline!", false, PAUSE, &no_abbreviations())
+            .with_prosody(&prosody);
+        assert_eq!(text.text, "Setup?\n\nPunchline!");
+        let neutral = Prosody {
+            rate: None,
+            pitch: None,
+        };
+        let text = SpeechText::new("Setup?", "Punchline!", true, PAUSE, &no_abbreviations())
+            .with_prosody(&neutral);
+        assert!(!text.text.contains("prosody"));
+    }
+
+    #[test]
+    fn speech_rate_and_pitch_are_validated() {
+        assert!(is_speech_rate("slow"));
+        assert!(is_speech_rate("85%"));
+        assert!(!is_speech_rate("10%"));
+        assert!(!is_speech_rate("85"));
+        assert!(!is_speech_rate("85%\" onload=\""));
+        assert!(is_speech_pitch("x-low"));
+        assert!(is_speech_pitch("+5.5%"));
+        assert!(is_speech_pitch("-10%"));
+        assert!(!is_speech_pitch("10%"));
+        assert!(!is_speech_pitch("+high%"));
+    }
+
+    #[test]
+    fn chunked_pauses_before_plain_punchline() {
+        let texts = setup_and_punchline(false, Some(PAUSE));
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[0].text, "Setup?");
+        assert_eq!(texts[0].pause_before(), Duration::from_secs(0));
+        assert_eq!(texts[1].text, "Punchline!");
+        assert_eq!(texts[1].pause_before(), PAUSE);
+
+        let texts = setup_and_punchline(false, Some(Duration::from_secs(0)));
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].text, "Setup?\n\nPunchline!");
+    }
+
+    #[test]
+    fn chunked_without_delay_is_a_single_request() {
+        let texts = setup_and_punchline(false, None);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].text, "Setup?\n\nPunchline!");
+
+        let texts = setup_and_punchline(true, None);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(
+            texts[0].text,
+            "<speak>Setup?<break time=\"700ms\"/>Punchline!</speak>"
+        );
+    }
+
+    #[test]
+    fn format_voices_aligns_columns() {
+        let voice = |id: &str, gender: &str, engines: &[&str]| rusoto_polly::Voice {
+            id: Some(id.to_string()),
+            gender: Some(gender.to_string()),
+            supported_engines: Some(engines.iter().map(|e| e.to_string()).collect()),
+            ..Default::default()
+        };
+        let voices = [
+            voice("Joanna", "Female", &["neural", "standard"]),
+            voice("Ivy", "Female", &["standard"]),
+            voice("Kevin", "Male", &["neural"]),
+        ];
+        assert_eq!(
+            format_voices(&voices),
+            "Voice   Gender  Engines\n\
+             Joanna  Female  neural, standard\n\
+             Ivy     Female  standard\n\
+             Kevin   Male    neural\n"
+        );
+    }
+
+    #[test]
+    fn filter_voices_by_gender_ignores_case() {
+        let voice = |id: &str, gender: &str| rusoto_polly::Voice {
+            id: Some(id.to_string()),
+            gender: Some(gender.to_string()),
+            ..Default::default()
+        };
+        let voices = || vec![voice("Matthew", "Male"), voice("Joanna", "Female")];
+        let ids = |voices: Vec<rusoto_polly::Voice>| {
+            voices.into_iter().filter_map(|v| v.id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(filter_voices_by_gender(voices(), Some("male"), "en-US").unwrap()),
+            vec!["Matthew"]
+        );
+        assert_eq!(
+            ids(filter_voices_by_gender(voices(), None, "en-US").unwrap()).len(),
+            2
+        );
+        let only_female = vec![voice("Joanna", "Female")];
+        assert!(filter_voices_by_gender(only_female, Some("male"), "en-US").is_err());
+    }
+
+    #[test]
+    fn voices_reject_unknown_voices() {
+        let voices = || {
+            ["Matthew", "Joanna"]
+                .iter()
+                .map(|id| rusoto_polly::Voice {
+                    id: Some(id.to_string()),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut rng = rand::thread_rng();
+        let pinned = Voices::with_choice(voices(), Some("Joanna"), None).unwrap();
+        assert_eq!(pinned.pick(&mut rng).id.as_deref(), Some("Joanna"));
+        let weighted = Voices::with_choice(voices(), None, Some(&[("Matthew", 2)])).unwrap();
+        assert_eq!(weighted.pick(&mut rng).id.as_deref(), Some("Matthew"));
+
+        assert!(Voices::with_choice(voices(), Some("Kevin"), None).is_err());
+        assert!(Voices::with_choice(voices(), None, Some(&[("Kevin", 1)])).is_err());
+    }
+
+    #[test]
+    fn parse_voice_weights_reads_list() {
+        assert_eq!(
+            parse_voice_weights("Matthew=3, Joanna=2,Kevin=1"),
+            Ok(vec![("Matthew", 3), ("Joanna", 2), ("Kevin", 1)])
+        );
+        assert!(parse_voice_weights("Matthew").is_err());
+        assert!(parse_voice_weights("Matthew=0").is_err());
+        assert!(parse_voice_weights("=3").is_err());
+    }
+
+    #[test]
+    fn split_into_chunks_covers_long_text() {
+        let sentence = "Why did the dad joke cross the road? To get to the punchline! ";
+        let text = sentence.repeat(5000 / sentence.len() + 1);
+        let text = text.trim();
+        assert!(text.chars().count() > 5000);
+
+        let chunks = split_into_chunks(text, POLLY_MAX_CHARS);
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chars().count() <= POLLY_MAX_CHARS));
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.ends_with('?') || chunk.ends_with('!')));
+        assert_eq!(chunks.join(" "), text);
+    }
+
+    #[test]
+    fn split_into_chunks_splits_long_sentences() {
+        let chunks = split_into_chunks("one two three four. five", 9);
+        assert_eq!(chunks, vec!["one two", "three", "four.", "five"]);
+    }
+
+    #[test]
+    fn split_into_chunks_counts_characters() {
+        let chunks = split_into_chunks("één twee drie", 13);
+        assert_eq!(chunks, vec!["één twee drie"]);
+        let chunks = split_into_chunks("ééééé ééééé", 5);
+        assert_eq!(chunks, vec!["ééééé", "ééééé"]);
+    }
+
+    #[test]
+    fn chunked_measures_expanded_abbreviations() {
+        let abbreviations = Abbreviations::from_table("& = and").unwrap();
+        // Just fits before expanding, every `&` is two characters longer read out
+        let selftext = "Salt & pepper. ".repeat((POLLY_MAX_CHARS - 10) / 15);
+        let selftext = selftext.trim();
+        assert!("Title".len() + 2 + selftext.chars().count() <= POLLY_MAX_CHARS);
+
+        let texts = SpeechText::chunked("Title", selftext, false, None, &abbreviations);
+        assert!(texts.len() > 1);
+        assert!(texts
+            .iter()
+            .all(|text| text.text.chars().count() <= POLLY_MAX_CHARS));
+        assert!(texts.iter().all(|text| !text.text.contains('&')));
+    }
+
+    #[test]
+    fn strip_urls_replaces_urls() {
+        let text = "See https://example.com/joke?id=1&x=2, or www.reddit.com/r/dadjokes.";
+        assert_eq!(strip_urls(text, UrlMode::Link), "See link, or link.");
+        assert_eq!(strip_urls(text, UrlMode::Remove), "See, or.");
+        assert_eq!(strip_urls(text, UrlMode::Keep), text);
+    }
+
+    #[test]
+    fn strip_urls_handles_url_shapes() {
+        assert_eq!(
+            strip_urls("(HTTP://EXAMPLE.COM/a_b) done", UrlMode::Link),
+            "(link) done"
+        );
+        assert_eq!(
+            strip_urls("http://example.com\nPunchline!", UrlMode::Remove),
+            "\nPunchline!"
+        );
+        assert_eq!(
+            strip_urls("https://i.redd.it/abc.jpg is me", UrlMode::Remove),
+            "is me"
+        );
+        assert_eq!(
+            strip_urls("No urls in example.com or httpsomething", UrlMode::Link),
+            "No urls in example.com or httpsomething"
+        );
+    }
+
+    #[test]
+    fn clean_selftext_unescapes_entities() {
+        assert_eq!(clean_selftext("Salt &amp; pepper"), "Salt & pepper");
+        assert_eq!(clean_selftext("1 &lt; 2 &gt; 0"), "1 < 2 > 0");
+        assert_eq!(
+            clean_selftext("Dad&#39;s &quot;joke&quot;"),
+            "Dad's \"joke\""
+        );
+        assert_eq!(
+            clean_selftext("Unknown &entity; & stuff"),
+            "Unknown &entity; & stuff"
+        );
+    }
+
+    #[test]
+    fn clean_selftext_strips_emphasis() {
+        assert_eq!(clean_selftext("It was **really** bad"), "It was really bad");
+        assert_eq!(
+            clean_selftext("It was *kind of* ~~good~~ bad"),
+            "It was kind of good bad"
+        );
+        assert_eq!(clean_selftext("# Punchline"), "Punchline");
+        assert_eq!(clean_selftext("Escaped \\*star\\*"), "Escaped *star*");
+        assert_eq!(clean_selftext("Run `make joke` now"), "Run make joke now");
+    }
+
+    #[test]
+    fn clean_selftext_keeps_lone_asterisks() {
+        assert_eq!(clean_selftext("2 * 3 = 6"), "2 * 3 = 6");
+        assert_eq!(clean_selftext("What the f*ck"), "What the f*ck");
+        assert_eq!(clean_selftext("f*ck this f*ck"), "f*ck this f*ck");
+        assert_eq!(clean_selftext("snake_case_name"), "snake_case_name");
+    }
+
+    #[test]
+    fn clean_selftext_keeps_link_text() {
+        assert_eq!(
+            clean_selftext("See [this joke](https://example.com/joke) for more"),
+            "See this joke for more"
+        );
+        assert_eq!(clean_selftext("[unclosed link"), "[unclosed link");
+    }
+
+    #[test]
+    fn clean_selftext_collapses_blank_lines() {
+        assert_eq!(
+            clean_selftext("Setup\n\n\n&amp;#x200B;\n\n\nPunchline!\n\n"),
+            "Setup\n\nPunchline!"
+        );
+    }
+
+    #[test]
+    fn clean_selftext_keeps_punctuation() {
+        let text = "Why? Because: it's 5-10 (ish), obviously; right!";
+        assert_eq!(clean_selftext(text), text);
+    }
+}
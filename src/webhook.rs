@@ -0,0 +1,64 @@
+use crate::reddit::RedditPost;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A webhook is only a nice extra, so it gets a lot less time than `HTTP_TIMEOUT`
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The chat service `WEBHOOK_URL` belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebhookFormat {
+    Discord,
+    Slack,
+}
+
+/// Where the jokes are announced
+pub struct WebhookConfig {
+    /// `None` to not announce the jokes
+    pub url: Option<&'static str>,
+    pub format: WebhookFormat,
+}
+
+/// Post `post` to the webhook of `config` with `client` in the background.
+///
+/// Does nothing if `WEBHOOK_URL` is not set. Errors are only logged, so the main loop never waits
+/// on the webhook.
+pub fn announce(config: &WebhookConfig, client: &reqwest::Client, post: &RedditPost) {
+    if let Some(url) = config.url {
+        let payload = payload(post, config.format);
+        let id = post.id.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let result = client
+                .post(url)
+                .json(&payload)
+                .timeout(WEBHOOK_TIMEOUT)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            match result {
+                Ok(_) => debug!("Announced joke {:?} on the webhook", id),
+                Err(e) => warn!("Could not announce joke {:?} on the webhook: {:?}", id, e),
+            }
+        });
+    }
+}
+
+/// The message for `post`, with the subreddit and score below the joke.
+fn payload(post: &RedditPost, format: WebhookFormat) -> Value {
+    match format {
+        WebhookFormat::Discord => json!({
+            "content": format!(
+                "**{}**\n{}\n*r/{}, score {}*",
+                post.title, post.selftext, post.subreddit, post.score
+            ),
+        }),
+        WebhookFormat::Slack => json!({
+            "text": format!(
+                "*{}*\n{}\n_r/{}, score {}_",
+                post.title, post.selftext, post.subreddit, post.score
+            ),
+        }),
+    }
+}